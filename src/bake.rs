@@ -0,0 +1,404 @@
+// src/bake.rs
+use bevy::math::Vec3;
+use std::f32::consts::{PI, TAU};
+
+use crate::export::obj::ObjMesh;
+use crate::field::SculptField;
+use crate::state::StitchingType;
+
+/// Turns a mesh back into a `width` x `height` sculpt field for `stitching_type`.
+///
+/// Every pixel casts a ray from inside the mesh (or from above it, for a
+/// plane) along the direction its grid position has on the ideal surface, and
+/// takes the outermost hit. The grid follows the layout `create_sculpt_mesh`
+/// meshes, so the baked map comes back out facing outwards. Shapes that are
+/// not star-shaped around the cast origin lose their hidden parts.
+pub fn bake_sculpt_map(
+    mesh: &ObjMesh,
+    stitching_type: StitchingType,
+    width: usize,
+    height: usize,
+) -> SculptField {
+    let triangles = Bvh::new(
+        mesh.triangles
+            .iter()
+            .map(|triangle| triangle.map(|vertex| Vec3::from(mesh.positions[vertex])))
+            .collect(),
+    );
+    let (min, max) = mesh
+        .positions
+        .iter()
+        .map(|&position| Vec3::from(position))
+        .fold((Vec3::MAX, Vec3::MIN), |(min, max), position| {
+            (min.min(position), max.max(position))
+        });
+    let center = (min + max) / 2.0;
+    let reach = (max - min).length() + 1.0;
+
+    // Torus rays start on the ring through the middle of the tube
+    let ring_radius = mesh
+        .positions
+        .iter()
+        .map(|&position| (Vec3::from(position) - center).truncate().length())
+        .sum::<f32>()
+        / mesh.positions.len().max(1) as f32;
+
+    let (wrap_x, wrap_y) = stitching_type.wrapped_axes();
+    let fraction = |i: usize, count: usize, wrap: bool| {
+        if wrap {
+            i as f32 / count as f32
+        } else {
+            i as f32 / (count - 1).max(1) as f32
+        }
+    };
+
+    let mut points = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let v = fraction(y, height, wrap_y);
+        for x in 0..width {
+            let u = fraction(x, width, wrap_x);
+            let angle = u * TAU;
+            let around = Vec3::new(angle.cos(), angle.sin(), 0.0);
+
+            // Rows run top to bottom, columns counter-clockwise around Z
+            let point = match stitching_type {
+                StitchingType::Plane => {
+                    let origin = Vec3::new(
+                        min.x + u * (max.x - min.x),
+                        max.y - v * (max.y - min.y),
+                        max.z + 1.0,
+                    );
+                    nearest_hit(&triangles, origin, Vec3::NEG_Z, reach)
+                        .unwrap_or(Vec3::new(origin.x, origin.y, min.z))
+                }
+                StitchingType::Sphere => {
+                    let polar = v * PI;
+                    let direction = around * polar.sin() + Vec3::Z * polar.cos();
+                    farthest_hit(&triangles, center, direction, reach).unwrap_or(center)
+                }
+                StitchingType::Cylinder => {
+                    let origin = Vec3::new(center.x, center.y, max.z - v * (max.z - min.z));
+                    farthest_hit(&triangles, origin, around, reach).unwrap_or(origin)
+                }
+                StitchingType::Torus => {
+                    let origin = center + around * ring_radius;
+                    let minor = -v * TAU;
+                    let direction = around * minor.cos() + Vec3::Z * minor.sin();
+                    // Never past the axis, or the far side of the ring is hit
+                    farthest_hit(&triangles, origin, direction, ring_radius.max(f32::EPSILON))
+                        .unwrap_or(origin)
+                }
+            };
+            points.push(point);
+        }
+    }
+
+    // Scale every axis by the largest extent, centred, so the baked shape
+    // keeps its proportions on a cube prim
+    let (low, high) = points
+        .iter()
+        .fold((Vec3::MAX, Vec3::MIN), |(low, high), &point| {
+            (low.min(point), high.max(point))
+        });
+    let middle = (low + high) / 2.0;
+    let range = (high - low).max_element().max(f32::EPSILON);
+    let samples = points
+        .iter()
+        .map(|&point| {
+            ((point - middle) / range + 0.5)
+                .clamp(Vec3::ZERO, Vec3::ONE)
+                .to_array()
+        })
+        .collect();
+
+    SculptField::new(width, height, samples)
+}
+
+fn nearest_hit(triangles: &Bvh, origin: Vec3, direction: Vec3, reach: f32) -> Option<Vec3> {
+    triangles
+        .ray_hits(origin, direction, reach)
+        .into_iter()
+        .min_by(f32::total_cmp)
+        .map(|distance| origin + direction * distance)
+}
+
+fn farthest_hit(triangles: &Bvh, origin: Vec3, direction: Vec3, reach: f32) -> Option<Vec3> {
+    triangles
+        .ray_hits(origin, direction, reach)
+        .into_iter()
+        .max_by(f32::total_cmp)
+        .map(|distance| origin + direction * distance)
+}
+
+/// Triangles a leaf of the hierarchy holds at most.
+const LEAF_TRIANGLES: usize = 4;
+
+/// Bounding volume hierarchy over the mesh triangles, so a ray is only tested
+/// against the triangles whose boxes it passes through.
+struct Bvh {
+    triangles: Vec<[Vec3; 3]>,
+    nodes: Vec<BvhNode>,
+}
+
+struct BvhNode {
+    min: Vec3,
+    max: Vec3,
+    /// Triangles `start..end` for a leaf, nothing for a branch.
+    start: usize,
+    end: usize,
+    /// Child node indices of a branch.
+    children: Option<[usize; 2]>,
+}
+
+impl Bvh {
+    fn new(mut triangles: Vec<[Vec3; 3]>) -> Self {
+        let mut bvh = Self {
+            triangles: Vec::new(),
+            nodes: Vec::new(),
+        };
+        if !triangles.is_empty() {
+            let count = triangles.len();
+            bvh.build(&mut triangles, 0, count);
+        }
+        bvh.triangles = triangles;
+        bvh
+    }
+
+    /// Adds the node over `triangles[start..end]`, sorting them so every
+    /// child covers a contiguous run, and returns its index.
+    fn build(&mut self, triangles: &mut [[Vec3; 3]], start: usize, end: usize) -> usize {
+        let (min, max) = triangles[start..end]
+            .iter()
+            .flatten()
+            .fold((Vec3::MAX, Vec3::MIN), |(min, max), &corner| {
+                (min.min(corner), max.max(corner))
+            });
+        let index = self.nodes.len();
+        self.nodes.push(BvhNode {
+            min,
+            max,
+            start,
+            end,
+            children: None,
+        });
+        if end - start <= LEAF_TRIANGLES {
+            return index;
+        }
+
+        // Split at the median centre along the longest side of the box
+        let extent = max - min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+        let middle = (start + end) / 2;
+        triangles[start..end].select_nth_unstable_by(middle - start, |a, b| {
+            let centre =
+                |triangle: &[Vec3; 3]| triangle.iter().map(|corner| corner[axis]).sum::<f32>();
+            centre(a).total_cmp(&centre(b))
+        });
+
+        let left = self.build(triangles, start, middle);
+        let right = self.build(triangles, middle, end);
+        self.nodes[index].children = Some([left, right]);
+        index
+    }
+
+    /// Distances along the ray, up to `reach`, at which it crosses a triangle.
+    fn ray_hits(&self, origin: Vec3, direction: Vec3, reach: f32) -> Vec<f32> {
+        let mut hits = Vec::new();
+        if self.nodes.is_empty() {
+            return hits;
+        }
+
+        let inverse = direction.recip();
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !ray_meets_box(node.min, node.max, origin, inverse, reach) {
+                continue;
+            }
+            match node.children {
+                Some(children) => stack.extend(children),
+                None => hits.extend(
+                    self.triangles[node.start..node.end]
+                        .iter()
+                        .filter_map(|&triangle| ray_hit(triangle, origin, direction, reach)),
+                ),
+            }
+        }
+        hits
+    }
+}
+
+/// Whether the ray passes through the box before `reach` (slab test).
+fn ray_meets_box(min: Vec3, max: Vec3, origin: Vec3, inverse: Vec3, reach: f32) -> bool {
+    let (mut enter, mut exit) = (0.0f32, reach);
+    for axis in 0..3 {
+        if inverse[axis].is_infinite() {
+            // Parallel to this slab, the origin has to lie within it
+            if origin[axis] < min[axis] || origin[axis] > max[axis] {
+                return false;
+            }
+            continue;
+        }
+        let to_min = (min[axis] - origin[axis]) * inverse[axis];
+        let to_max = (max[axis] - origin[axis]) * inverse[axis];
+        enter = enter.max(to_min.min(to_max));
+        exit = exit.min(to_min.max(to_max));
+    }
+    enter <= exit
+}
+
+/// Distance along the ray, up to `reach`, at which it crosses the triangle
+/// (Möller–Trumbore).
+fn ray_hit([a, b, c]: [Vec3; 3], origin: Vec3, direction: Vec3, reach: f32) -> Option<f32> {
+    const EPSILON: f32 = 1e-7;
+
+    let edge1 = b - a;
+    let edge2 = c - a;
+    let p = direction.cross(edge2);
+    let determinant = edge1.dot(p);
+    if determinant.abs() < EPSILON {
+        return None;
+    }
+
+    let inverse = 1.0 / determinant;
+    let to_origin = origin - a;
+    let u = to_origin.dot(p) * inverse;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = to_origin.cross(edge1);
+    let v = direction.dot(q) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let distance = edge2.dot(q) * inverse;
+    (distance > EPSILON && distance <= reach).then_some(distance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A UV sphere of radius one, with plenty of triangles for the hierarchy to split.
+    fn sphere_triangles() -> Vec<[Vec3; 3]> {
+        let point = |i: usize, j: usize| {
+            let (polar, angle) = (j as f32 / 12.0 * PI, i as f32 / 24.0 * TAU);
+            Vec3::new(
+                polar.sin() * angle.cos(),
+                polar.sin() * angle.sin(),
+                polar.cos(),
+            )
+        };
+        (0..24)
+            .flat_map(|i| (0..12).map(move |j| (i, j)))
+            .flat_map(|(i, j)| {
+                [
+                    [point(i, j), point(i + 1, j), point(i + 1, j + 1)],
+                    [point(i, j), point(i + 1, j + 1), point(i, j + 1)],
+                ]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn hierarchy_finds_the_same_hits_as_every_triangle() {
+        let triangles = sphere_triangles();
+        let bvh = Bvh::new(triangles.clone());
+        let rays = [
+            (Vec3::ZERO, Vec3::X),
+            (Vec3::ZERO, Vec3::new(0.3, -0.5, 0.8).normalize()),
+            (Vec3::new(0.2, 0.1, 3.0), Vec3::NEG_Z),
+            (Vec3::new(-3.0, 0.5, 0.0), Vec3::X),
+            (Vec3::new(2.0, 2.0, 2.0), Vec3::X),
+        ];
+
+        for (origin, direction) in rays {
+            let mut expected: Vec<f32> = triangles
+                .iter()
+                .filter_map(|&triangle| ray_hit(triangle, origin, direction, 10.0))
+                .collect();
+            let mut found = bvh.ray_hits(origin, direction, 10.0);
+            expected.sort_by(f32::total_cmp);
+            found.sort_by(f32::total_cmp);
+            assert_eq!(found, expected, "ray from {} along {}", origin, direction);
+        }
+    }
+
+    #[test]
+    fn reach_cuts_off_far_hits() {
+        let bvh = Bvh::new(sphere_triangles());
+        assert!(
+            bvh.ray_hits(Vec3::new(0.0, 0.0, 3.0), Vec3::NEG_Z, 1.5)
+                .is_empty()
+        );
+        assert_eq!(
+            bvh.ray_hits(Vec3::new(0.1, 0.05, 3.0), Vec3::NEG_Z, 3.5)
+                .len(),
+            1
+        );
+    }
+
+    /// An open cylinder of radius one, four long along Z, as an OBJ.
+    fn cylinder_obj() -> Vec<u8> {
+        const SIDES: usize = 32;
+        let mut obj = String::new();
+        for i in 0..SIDES {
+            let angle = i as f32 / SIDES as f32 * TAU;
+            for z in [-2.0, 2.0] {
+                obj += &format!("v {} {} {}\n", angle.cos(), angle.sin(), z);
+            }
+        }
+        for i in 0..SIDES {
+            let (bottom, top) = (2 * i + 1, 2 * i + 2);
+            let (next_bottom, next_top) = (2 * ((i + 1) % SIDES) + 1, 2 * ((i + 1) % SIDES) + 2);
+            obj += &format!("f {} {} {} {}\n", bottom, next_bottom, next_top, top);
+        }
+        obj.into_bytes()
+    }
+
+    #[test]
+    fn baked_cylinder_keeps_its_proportions() {
+        let mesh = crate::export::obj::read_obj(&cylinder_obj()).unwrap();
+        let (width, height) = (16, 9);
+        let field = bake_sculpt_map(&mesh, StitchingType::Cylinder, width, height);
+
+        for y in 0..height {
+            for x in 0..width {
+                let [r, g, b] = field.sample(x, y);
+                // Rows run from the top of the cylinder down
+                assert!((b - (1.0 - y as f32 / (height - 1) as f32)).abs() < 1e-5);
+                if y == 0 || y == height - 1 {
+                    continue;
+                }
+                // Two across and four along, so the radius is an eighth of the map
+                let (dx, dy) = (r - 0.5, g - 0.5);
+                let radius = (dx * dx + dy * dy).sqrt();
+                assert!(
+                    (radius - 0.25).abs() < 0.01,
+                    "radius {} at {}, {}",
+                    radius,
+                    x,
+                    y
+                );
+                // Columns run counter-clockwise around Z, starting on +X
+                let angle = dy.atan2(dx).rem_euclid(TAU);
+                let expected = x as f32 / width as f32 * TAU;
+                let turn = (angle - expected).rem_euclid(TAU);
+                assert!(
+                    turn.min(TAU - turn) < 0.05,
+                    "angle {} at {}, {}",
+                    angle,
+                    x,
+                    y
+                );
+            }
+        }
+    }
+}
@@ -4,7 +4,7 @@ use neat::rand::Rng;
 use std::collections::HashMap;
 use std::mem;
 
-use crate::{Selectable, generator, sculpt, state};
+use crate::{ReferenceTile, Selectable, sculpt, state, ui};
 
 pub fn log_activation_distribution(mut evo_state: ResMut<state::EvoState>) {
     if !evo_state.debug_requested {
@@ -57,60 +57,185 @@ pub fn evolve_system(mut evo_state: ResMut<state::EvoState>) {
     }
     let target_pop_size = evo_state.get_population_size();
     let genomes = mem::take(&mut evo_state.genomes);
+    let textures = mem::take(&mut evo_state.textures);
     let fitnesses = mem::take(&mut evo_state.fitness);
-    let population_with_fitness: Vec<_> = genomes.into_iter().zip(fitnesses).collect();
+    // A tile's shape and texture are bred together, from the same parents
+    let population_with_fitness: Vec<_> =
+        genomes.into_iter().zip(textures).zip(fitnesses).collect();
     let champions: Vec<_> = population_with_fitness
         .iter()
         .filter(|(_, fitness)| *fitness > 0.0)
-        .map(|(genome, _)| genome.clone())
+        .map(|(tile, _)| tile.clone())
         .collect();
     let parents = if champions.is_empty() {
         population_with_fitness
             .into_iter()
-            .map(|(genome, _)| genome)
+            .map(|(tile, _)| tile)
             .collect()
     } else {
         champions
     };
     let mut rng = neat::rand::thread_rng();
     let mut next_generation = Vec::with_capacity(target_pop_size);
+    let mut next_textures = Vec::with_capacity(target_pop_size);
 
     while next_generation.len() < target_pop_size {
-        let parent1 = &parents[rng.gen_range(0..parents.len())];
-        let parent2 = &parents[rng.gen_range(0..parents.len())];
+        let (genome1, texture1) = &parents[rng.gen_range(0..parents.len())];
+        let (genome2, texture2) = &parents[rng.gen_range(0..parents.len())];
 
-        let child = parent1.crossover(parent2, &mut rng);
-        next_generation.push(child);
+        next_generation.push(genome1.crossover(genome2, &mut rng));
+        next_textures.push(texture1.crossover(texture2, &mut rng));
     }
 
     evo_state.genomes = next_generation;
+    evo_state.textures = next_textures;
     evo_state.generation += 1;
     evo_state.fitness = vec![0.0; target_pop_size];
     evo_state.evolution_requested = false;
+    evo_state.clear_map_reports();
     evo_state.redraw_requested = true;
 }
 
+/// Samples evaluated per frame while refining tiles, as many as four 64x64 maps.
+const REFINE_SAMPLES_PER_FRAME: usize = 4 * 64 * 64;
+
+/// Rebuilds the tile meshes at the coarse resolution when a redraw is
+/// requested, then refines a few tiles per frame up to the preview resolution
+/// so large grids stay responsive.
 pub fn update_meshes_system(
-    mut query: Query<(&mut Selectable, &Mesh3d)>,
+    mut query: Query<(&mut Selectable, &Mesh3d, &MeshMaterial3d<StandardMaterial>)>,
+    reference_query: Query<(&ReferenceTile, &Mesh3d)>,
     mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut images: ResMut<Assets<Image>>,
     mut evo_state: ResMut<state::EvoState>,
 ) {
-    if evo_state.redraw_requested
-    {
-        for (mut selectable, mesh_handle) in query.iter_mut() {
+    if evo_state.redraw_requested {
+        let resolution = evo_state.coarse_resolution();
+        for (mut selectable, mesh_handle, material_handle) in query.iter_mut() {
+            // Tiles of a grid that was just shrunk linger until their despawn is applied
+            let Some(new_topology) = evo_state.genomes.get(selectable.index) else {
+                continue;
+            };
             if let Some(mesh) = meshes.get_mut(mesh_handle) {
-                let new_topology = &evo_state.genomes[selectable.index];
-
-                let image = generator::generate_image_from_topology(new_topology);
-                let sculpt_data = sculpt::create_sculpt_mesh(&image, 5.0, evo_state.stitching_type);
+                let sculpt_data =
+                    sculpt::create_genome_preview_mesh(new_topology, &evo_state, resolution);
+                sculpt::insert_preview_attributes(mesh, &sculpt_data);
 
-                mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, sculpt_data.vertices);
-                mesh.insert_indices(sculpt_data.indices);
-                mesh.compute_smooth_normals();
-
-                selectable.is_selected = false;
+                selectable.is_selected = evo_state
+                    .fitness
+                    .get(selectable.index)
+                    .is_some_and(|&f| f > 0.0);
+                selectable.resolution = resolution;
+            }
+            if let Some(material) = materials.get_mut(&material_handle.0) {
+                ui::apply_tile_texture(material, &mut images, &evo_state, selectable.index);
+            }
+        }
+        for (reference, mesh_handle) in reference_query.iter() {
+            if let Some(mesh) = meshes.get_mut(mesh_handle)
+                && let Some(reference_map) = evo_state.reference_maps.get(reference.index)
+            {
+                let field = &reference_map.field;
+                let sculpt_data = sculpt::create_preview_mesh(field, &evo_state);
+                sculpt::insert_preview_attributes(mesh, &sculpt_data);
             }
         }
         evo_state.redraw_requested = false;
+        return;
+    }
+
+    // Refine the coarse tiles in grid order, as many as the frame budget allows
+    let resolution = evo_state.preview_resolution;
+    let [width, height] = evo_state.sculpt_aspect.dimensions(resolution);
+    let budget = (REFINE_SAMPLES_PER_FRAME / (width * height)).max(1);
+    let mut coarse: Vec<_> = query
+        .iter_mut()
+        .filter(|(selectable, _, _)| {
+            selectable.resolution != resolution && selectable.index < evo_state.genomes.len()
+        })
+        .collect();
+    coarse.sort_by_key(|(selectable, _, _)| selectable.index);
+    for (mut selectable, mesh_handle, _) in coarse.into_iter().take(budget) {
+        if let Some(mesh) = meshes.get_mut(mesh_handle) {
+            let topology = &evo_state.genomes[selectable.index];
+            let sculpt_data = sculpt::create_genome_preview_mesh(topology, &evo_state, resolution);
+            sculpt::insert_preview_attributes(mesh, &sculpt_data);
+            selectable.resolution = resolution;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+
+    /// A world holding the tiles of the default 4x4 grid, meshed coarse.
+    fn grid_world() -> World {
+        let mut world = World::new();
+        let evo_state = state::EvoState::default();
+        let resolution = evo_state.coarse_resolution();
+        let mut meshes = Assets::<Mesh>::default();
+        let mut materials = Assets::<StandardMaterial>::default();
+        for index in 0..evo_state.get_population_size() {
+            let mesh = meshes.add(Mesh::new(
+                bevy::mesh::PrimitiveTopology::TriangleList,
+                default(),
+            ));
+            let material = materials.add(StandardMaterial::default());
+            world.spawn((
+                Mesh3d(mesh),
+                MeshMaterial3d(material),
+                Selectable {
+                    index,
+                    is_selected: false,
+                    resolution,
+                },
+            ));
+        }
+        world.insert_resource(meshes);
+        world.insert_resource(materials);
+        world.insert_resource(Assets::<Image>::default());
+        world.insert_resource(evo_state);
+        world
+    }
+
+    fn tile_resolutions(world: &mut World) -> Vec<(usize, usize)> {
+        let mut tiles: Vec<_> = world
+            .query::<&Selectable>()
+            .iter(world)
+            .map(|selectable| (selectable.index, selectable.resolution))
+            .collect();
+        tiles.sort();
+        tiles
+    }
+
+    #[test]
+    fn shrinking_the_grid_mid_refinement_skips_stale_tiles() {
+        let mut world = grid_world();
+        {
+            let mut evo_state = world.resource_mut::<state::EvoState>();
+            evo_state.redraw_requested = false;
+            evo_state.resize_grid(2);
+        }
+        // The old tiles are still alive, as they are until spawn_grid_system's despawns apply
+        world.run_system_once(update_meshes_system).unwrap();
+
+        let preview = world.resource::<state::EvoState>().preview_resolution;
+        let coarse = world.resource::<state::EvoState>().coarse_resolution();
+        let tiles = tile_resolutions(&mut world);
+        assert_eq!(tiles.len(), 16);
+        for (index, resolution) in tiles {
+            assert_eq!(resolution, if index < 4 { preview } else { coarse });
+        }
+    }
+
+    #[test]
+    fn redrawing_a_shrunk_grid_skips_stale_tiles() {
+        let mut world = grid_world();
+        world.resource_mut::<state::EvoState>().resize_grid(2);
+        world.run_system_once(update_meshes_system).unwrap();
+        assert!(!world.resource::<state::EvoState>().redraw_requested);
     }
 }
@@ -0,0 +1,117 @@
+// src/export.rs
+pub mod collada;
+pub mod gltf;
+pub mod land_impact;
+pub mod metadata;
+pub mod obj;
+pub mod quantisation;
+pub mod sculpt_map;
+pub mod stl;
+pub mod validate;
+
+use crate::{generator, sculpt, session, state};
+
+/// Base name shared by every file exported for a tile, e.g. `sculpt_gen012_tile03`.
+pub fn tile_file_stem(generation: u64, index: usize) -> String {
+    format!("sculpt_gen{:03}_tile{:02}", generation, index)
+}
+
+/// JSON sidecar describing where the exported sculpt map of tile `index` came from.
+pub fn tile_sidecar(evo_state: &state::EvoState, index: usize) -> Vec<u8> {
+    let resolution = evo_state
+        .sculpt_aspect
+        .dimensions(evo_state.sculpt_map_settings.size);
+    metadata::SculptMetadata::new(
+        &evo_state.genomes[index],
+        evo_state.generation,
+        index,
+        evo_state.image_settings(),
+        evo_state.sculpt_flags,
+        resolution,
+    )
+    .to_bytes()
+}
+
+/// Name and PNG contents of the companion texture of tile `index`.
+pub fn tile_texture(evo_state: &state::EvoState, index: usize) -> (String, Vec<u8>) {
+    let texture = generator::generate_texture(
+        &evo_state.textures[index],
+        evo_state.image_settings(),
+        generator::TEXTURE_EXPORT_RESOLUTION,
+    );
+    let bytes = sculpt_map::encode_image(
+        &sculpt_map::field_to_dynamic(&texture),
+        sculpt_map::SculptMapFormat::Png,
+    );
+    (
+        format!(
+            "{}_diffuse.png",
+            tile_file_stem(evo_state.generation, index)
+        ),
+        bytes,
+    )
+}
+
+/// Builds the sculpt map of every selected tile, plus its metadata sidecar,
+/// texture, OBJ mesh and genome when enabled. Returns `(file name, contents)` pairs,
+/// or the reports of the maps that fail validation unless `include_invalid` is set.
+pub fn batch_export_files(
+    evo_state: &state::EvoState,
+    include_invalid: bool,
+) -> Result<Vec<(String, Vec<u8>)>, Vec<validate::MapReport>> {
+    let settings = &evo_state.sculpt_map_settings;
+    let mut files = Vec::new();
+    let mut invalid = Vec::new();
+
+    for index in evo_state.selected_indices() {
+        let topology = &evo_state.genomes[index];
+        let stem = tile_file_stem(evo_state.generation, index);
+
+        let image = sculpt_map::render_sculpt_map(topology, settings, evo_state.image_settings());
+        let report = validate::validate_sculpt_map(image, evo_state.stitching_type, index);
+        files.push((
+            format!("{}.{}", stem, settings.format.extension()),
+            sculpt_map::encode_image(&report.image, settings.format),
+        ));
+        if !report.is_valid() {
+            invalid.push(report);
+        }
+
+        if settings.sidecar {
+            files.push((format!("{}.json", stem), tile_sidecar(evo_state, index)));
+        }
+
+        if evo_state.texture_enabled {
+            files.push(tile_texture(evo_state, index));
+        }
+
+        if evo_state.batch_include_mesh {
+            let field = generator::generate_field_from_topology(
+                topology,
+                evo_state.image_settings(),
+                evo_state.preview_resolution,
+            );
+            // Export at unit size, like a 1m prim in-world
+            let sculpt_data = sculpt::create_sculpt_mesh(
+                &field,
+                1.0,
+                evo_state.stitching_type,
+                evo_state.sculpt_flags,
+            );
+            files.push((format!("{}.obj", stem), obj::write_obj(&sculpt_data, &stem)));
+        }
+
+        if evo_state.batch_include_genome {
+            files.push((
+                format!("{}.genome", stem),
+                session::genome_to_bytes(evo_state, index),
+            ));
+        }
+    }
+
+    if invalid.is_empty() || include_invalid {
+        Ok(files)
+    } else {
+        Err(invalid)
+    }
+}
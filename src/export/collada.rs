@@ -0,0 +1,343 @@
+use std::fmt::Write;
+
+use crate::field::SculptField;
+use crate::sculpt::{self, SculptMeshData};
+use crate::state::{SculptFlags, StitchingType};
+
+/// Lower detail levels, named the way the Second Life uploader picks them up
+/// from next to the high LOD file, with the divisor applied to the map size.
+const LOD_LEVELS: [(&str, usize); 3] = [("_LOD2", 2), ("_LOD1", 4), ("_LOD0", 8)];
+
+/// Meshes `field` and writes it as COLLADA, followed by the lower LOD files
+/// sampled from smaller versions of the map when `include_lods` is set.
+/// Returns `(file name, contents)` pairs.
+pub fn write_dae_with_lods(
+    field: &SculptField,
+    stitching_type: StitchingType,
+    flags: SculptFlags,
+    name: &str,
+    include_lods: bool,
+) -> Vec<(String, Vec<u8>)> {
+    // Meshes are uploaded at unit size and scaled in-world like any prim
+    let sculpt_data = sculpt::create_sculpt_mesh(field, 1.0, stitching_type, flags);
+    let mut files = vec![(format!("{}.dae", name), write_dae(&sculpt_data, name))];
+
+    if include_lods {
+        for (suffix, divisor) in LOD_LEVELS {
+            let width = (field.width() / divisor).max(2);
+            let height = (field.height() / divisor).max(2);
+            let lod_field = sculpt::downsample_field(field, width, height, stitching_type);
+            let lod_data = sculpt::create_sculpt_mesh(&lod_field, 1.0, stitching_type, flags);
+            let lod_name = format!("{}{}", name, suffix);
+            files.push((format!("{}.dae", lod_name), write_dae(&lod_data, &lod_name)));
+        }
+    }
+
+    files
+}
+
+/// Writes a sculpt mesh as a COLLADA 1.4.1 document with one material slot.
+pub fn write_dae(data: &SculptMeshData, name: &str) -> Vec<u8> {
+    let normals = sculpt::compute_smooth_normals(data);
+    let (uvs, uv_indices) = sculpt::compute_sculpt_uvs(data);
+    let corners: Vec<usize> = data.indices.iter().collect();
+
+    let mut triangles = String::new();
+    let mut triangle_count = 0;
+    for (triangle, uv_triangle) in corners.chunks_exact(3).zip(uv_indices.chunks_exact(3)) {
+        if sculpt::is_collapsed_triangle(triangle) {
+            continue;
+        }
+        for (vertex, uv) in triangle.iter().zip(uv_triangle) {
+            write!(triangles, "{} {} ", vertex, uv).unwrap();
+        }
+        triangle_count += 1;
+    }
+
+    let timestamp = timestamp();
+    let mut dae = String::new();
+    writeln!(dae, r#"<?xml version="1.0" encoding="utf-8"?>"#).unwrap();
+    writeln!(
+        dae,
+        r#"<COLLADA xmlns="http://www.collada.org/2005/11/COLLADASchema" version="1.4.1">"#
+    )
+    .unwrap();
+
+    writeln!(dae, "  <asset>").unwrap();
+    writeln!(
+        dae,
+        "    <contributor><authoring_tool>Evo-Sculptor</authoring_tool></contributor>"
+    )
+    .unwrap();
+    writeln!(dae, "    <created>{}</created>", timestamp).unwrap();
+    writeln!(dae, "    <modified>{}</modified>", timestamp).unwrap();
+    writeln!(dae, r#"    <unit name="meter" meter="1"/>"#).unwrap();
+    // Sculpt maps store Z in blue, and Z is up in-world
+    writeln!(dae, "    <up_axis>Z_UP</up_axis>").unwrap();
+    writeln!(dae, "  </asset>").unwrap();
+
+    writeln!(dae, "  <library_effects>").unwrap();
+    writeln!(dae, r#"    <effect id="sculpt-effect">"#).unwrap();
+    writeln!(
+        dae,
+        r#"      <profile_COMMON><technique sid="common"><lambert><diffuse><color sid="diffuse">0.8 0.7 0.6 1</color></diffuse></lambert></technique></profile_COMMON>"#
+    )
+    .unwrap();
+    writeln!(dae, "    </effect>").unwrap();
+    writeln!(dae, "  </library_effects>").unwrap();
+
+    writeln!(dae, "  <library_materials>").unwrap();
+    writeln!(
+        dae,
+        r##"    <material id="sculpt-material" name="sculpt"><instance_effect url="#sculpt-effect"/></material>"##
+    )
+    .unwrap();
+    writeln!(dae, "  </library_materials>").unwrap();
+
+    writeln!(dae, "  <library_geometries>").unwrap();
+    writeln!(
+        dae,
+        r#"    <geometry id="sculpt-mesh" name="{}"><mesh>"#,
+        name
+    )
+    .unwrap();
+    write_source(
+        &mut dae,
+        "sculpt-mesh-positions",
+        &data.vertices,
+        &["X", "Y", "Z"],
+    );
+    write_source(&mut dae, "sculpt-mesh-normals", &normals, &["X", "Y", "Z"]);
+    write_source(&mut dae, "sculpt-mesh-uvs", &uvs, &["S", "T"]);
+    writeln!(
+        dae,
+        r##"      <vertices id="sculpt-mesh-vertices"><input semantic="POSITION" source="#sculpt-mesh-positions"/><input semantic="NORMAL" source="#sculpt-mesh-normals"/></vertices>"##
+    )
+    .unwrap();
+    writeln!(
+        dae,
+        r#"      <triangles material="sculpt-material" count="{}">"#,
+        triangle_count
+    )
+    .unwrap();
+    writeln!(
+        dae,
+        r##"        <input semantic="VERTEX" source="#sculpt-mesh-vertices" offset="0"/>"##
+    )
+    .unwrap();
+    writeln!(
+        dae,
+        r##"        <input semantic="TEXCOORD" source="#sculpt-mesh-uvs" offset="1" set="0"/>"##
+    )
+    .unwrap();
+    writeln!(dae, "        <p>{}</p>", triangles.trim_end()).unwrap();
+    writeln!(dae, "      </triangles>").unwrap();
+    writeln!(dae, "    </mesh></geometry>").unwrap();
+    writeln!(dae, "  </library_geometries>").unwrap();
+
+    writeln!(dae, "  <library_visual_scenes>").unwrap();
+    writeln!(dae, r#"    <visual_scene id="Scene" name="Scene">"#).unwrap();
+    writeln!(dae, r#"      <node id="{0}" name="{0}" type="NODE">"#, name).unwrap();
+    writeln!(
+        dae,
+        r##"        <instance_geometry url="#sculpt-mesh"><bind_material><technique_common><instance_material symbol="sculpt-material" target="#sculpt-material"/></technique_common></bind_material></instance_geometry>"##
+    )
+    .unwrap();
+    writeln!(dae, "      </node>").unwrap();
+    writeln!(dae, "    </visual_scene>").unwrap();
+    writeln!(dae, "  </library_visual_scenes>").unwrap();
+    writeln!(
+        dae,
+        r##"  <scene><instance_visual_scene url="#Scene"/></scene>"##
+    )
+    .unwrap();
+    writeln!(dae, "</COLLADA>").unwrap();
+
+    dae.into_bytes()
+}
+
+/// Writes a `<source>` holding one float array with an accessor of the given parameter names.
+fn write_source<const N: usize>(dae: &mut String, id: &str, values: &[[f32; N]], params: &[&str]) {
+    let mut array = String::new();
+    for value in values {
+        for component in value {
+            write!(array, "{} ", component).unwrap();
+        }
+    }
+
+    writeln!(dae, r#"      <source id="{}">"#, id).unwrap();
+    writeln!(
+        dae,
+        r#"        <float_array id="{}-array" count="{}">{}</float_array>"#,
+        id,
+        values.len() * N,
+        array.trim_end()
+    )
+    .unwrap();
+    writeln!(
+        dae,
+        r##"        <technique_common><accessor source="#{}-array" count="{}" stride="{}">"##,
+        id,
+        values.len(),
+        N
+    )
+    .unwrap();
+    for param in params {
+        writeln!(dae, r#"          <param name="{}" type="float"/>"#, param).unwrap();
+    }
+    writeln!(dae, "        </accessor></technique_common>").unwrap();
+    writeln!(dae, "      </source>").unwrap();
+}
+
+/// Current UTC time as an `xs:dateTime`, as required by `<created>` and `<modified>`.
+fn timestamp() -> String {
+    let seconds = unix_time_secs();
+    let days = seconds.div_euclid(86_400);
+    let time_of_day = seconds.rem_euclid(86_400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time_of_day / 3600,
+        time_of_day % 3600 / 60,
+        time_of_day % 60
+    )
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn unix_time_secs() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn unix_time_secs() -> i64 {
+    (js_sys::Date::now() / 1000.0) as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(width: usize, height: usize) -> SculptField {
+        let samples = (0..width * height)
+            .map(|i| {
+                let (x, y) = ((i % width) as f32, (i / width) as f32);
+                [x / width as f32, y / height as f32, 0.5]
+            })
+            .collect();
+        SculptField::new(width, height, samples)
+    }
+
+    /// Value of `attribute` on the element that opens with `tag`.
+    fn attribute<'a>(dae: &'a str, tag: &str, attribute: &str) -> &'a str {
+        let element = &dae[dae.find(tag).unwrap()..];
+        let element = &element[..element.find('>').unwrap()];
+        let value = &element[element.find(&format!(r#"{}=""#, attribute)).unwrap()..];
+        value.split('"').nth(1).unwrap()
+    }
+
+    fn count(dae: &str, tag: &str) -> usize {
+        attribute(dae, tag, "count").parse().unwrap()
+    }
+
+    /// The vertex and UV index pairs of the triangle list.
+    fn corners(dae: &str) -> Vec<(usize, usize)> {
+        let list = &dae[dae.find("<p>").unwrap() + 3..dae.find("</p>").unwrap()];
+        let numbers: Vec<usize> = list
+            .split_whitespace()
+            .map(|n| n.parse().unwrap())
+            .collect();
+        numbers
+            .chunks_exact(2)
+            .map(|pair| (pair[0], pair[1]))
+            .collect()
+    }
+
+    #[test]
+    fn dae_counts_match_the_mesh() {
+        let (width, height) = (8, 6);
+        let data = sculpt::create_sculpt_mesh(
+            &field(width, height),
+            1.0,
+            StitchingType::Sphere,
+            SculptFlags::default(),
+        );
+        let bytes = write_dae(&data, "tile");
+        let dae = std::str::from_utf8(&bytes).unwrap();
+
+        let vertices = count(dae, r#"<float_array id="sculpt-mesh-positions-array""#) / 3;
+        assert_eq!(vertices, width * height);
+        assert_eq!(
+            count(dae, r#"<float_array id="sculpt-mesh-normals-array""#),
+            3 * vertices
+        );
+        let uvs = count(dae, r#"<float_array id="sculpt-mesh-uvs-array""#) / 2;
+
+        // The collapsed pole triangles are left out
+        let triangles = count(dae, "<triangles");
+        assert_eq!(triangles, 2 * width * (height - 2));
+        let corners = corners(dae);
+        assert_eq!(corners.len(), 3 * triangles);
+        assert!(
+            corners
+                .iter()
+                .all(|&(vertex, uv)| vertex < vertices && uv < uvs)
+        );
+        assert_eq!(dae.matches("<source ").count(), 3);
+        assert!(dae.trim_end().ends_with("</COLLADA>"));
+    }
+
+    #[test]
+    fn lods_are_named_and_smaller() {
+        let files = write_dae_with_lods(
+            &field(32, 32),
+            StitchingType::Cylinder,
+            SculptFlags::default(),
+            "tile",
+            true,
+        );
+        let names: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "tile.dae",
+                "tile_LOD2.dae",
+                "tile_LOD1.dae",
+                "tile_LOD0.dae"
+            ]
+        );
+
+        let triangles: Vec<usize> = files
+            .iter()
+            .map(|(_, bytes)| count(std::str::from_utf8(bytes).unwrap(), "<triangles"))
+            .collect();
+        // A cylinder map of w x h has 2w(h - 1) triangles
+        assert_eq!(triangles, [2 * 32 * 31, 2 * 16 * 15, 2 * 8 * 7, 2 * 4 * 3]);
+
+        let single = write_dae_with_lods(
+            &field(32, 32),
+            StitchingType::Cylinder,
+            SculptFlags::default(),
+            "tile",
+            false,
+        );
+        assert_eq!(single.len(), 1);
+    }
+}
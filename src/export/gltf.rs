@@ -0,0 +1,406 @@
+use bevy::prelude::*;
+use serde_json::{Value, json};
+
+use crate::sculpt::{self, SculptMeshData, UnweldedMesh};
+use crate::{Selectable, generator, io, state};
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const COMPONENT_FLOAT: u32 = 5126;
+const COMPONENT_UNSIGNED_INT: u32 = 5125;
+
+/// One grid tile to be written as its own node of the glTF scene.
+pub struct GltfTile {
+    pub name: String,
+    pub data: SculptMeshData,
+    pub translation: [f32; 3],
+    pub base_color: [f32; 4],
+    /// PNG painted over the base colour, embedded in the binary chunk.
+    pub texture: Option<Vec<u8>>,
+    pub metallic: f32,
+    pub roughness: f32,
+}
+
+/// Writes the selected tiles into a single GLB file when requested.
+pub fn export_glb_system(
+    mut evo_state: ResMut<state::EvoState>,
+    materials: Res<Assets<StandardMaterial>>,
+    query: Query<(&Selectable, &Transform, &MeshMaterial3d<StandardMaterial>)>,
+) {
+    if !evo_state.glb_export_requested {
+        return;
+    }
+    evo_state.glb_export_requested = false;
+
+    let mut tiles: Vec<(usize, GltfTile)> = Vec::new();
+    for (selectable, transform, material_handle) in &query {
+        if !evo_state
+            .fitness
+            .get(selectable.index)
+            .is_some_and(|&f| f > 0.0)
+        {
+            continue;
+        }
+        let Some(material) = materials.get(&material_handle.0) else {
+            continue;
+        };
+
+        let field = generator::generate_field_from_topology(
+            &evo_state.genomes[selectable.index],
+            evo_state.image_settings(),
+            evo_state.preview_resolution,
+        );
+        let data = sculpt::create_sculpt_mesh(
+            &field,
+            5.0,
+            evo_state.stitching_type,
+            evo_state.sculpt_flags,
+        );
+        let color = material.base_color.to_linear();
+        // The preview tints textured tiles white, the texture carries the colour
+        let texture = evo_state
+            .texture_enabled
+            .then(|| super::tile_texture(&evo_state, selectable.index).1);
+
+        tiles.push((
+            selectable.index,
+            GltfTile {
+                name: super::tile_file_stem(evo_state.generation, selectable.index),
+                data,
+                translation: transform.translation.to_array(),
+                base_color: [color.red, color.green, color.blue, color.alpha],
+                texture,
+                metallic: material.metallic,
+                roughness: material.perceptual_roughness,
+            },
+        ));
+    }
+
+    if tiles.is_empty() {
+        return;
+    }
+
+    tiles.sort_by_key(|(index, _)| *index);
+    let tiles: Vec<GltfTile> = tiles.into_iter().map(|(_, tile)| tile).collect();
+    let bytes = write_glb(&tiles);
+    io::save_file(
+        bytes,
+        &format!("sculpts_gen{:03}.glb", evo_state.generation),
+        io::GLB_FILTER,
+    );
+}
+
+/// Writes the tiles as a binary glTF 2.0 file, one mesh, material and node per tile.
+pub fn write_glb(tiles: &[GltfTile]) -> Vec<u8> {
+    let mut bin: Vec<u8> = Vec::new();
+    let mut buffer_views: Vec<Value> = Vec::new();
+    let mut accessors: Vec<Value> = Vec::new();
+    let mut meshes: Vec<Value> = Vec::new();
+    let mut materials: Vec<Value> = Vec::new();
+    let mut nodes: Vec<Value> = Vec::new();
+    let mut images: Vec<Value> = Vec::new();
+
+    for (i, tile) in tiles.iter().enumerate() {
+        let UnweldedMesh {
+            positions,
+            normals,
+            uvs,
+            indices,
+        } = sculpt::unweld_uv_seams(&tile.data);
+
+        let (min, max) = positions.iter().fold(
+            ([f32::MAX; 3], [f32::MIN; 3]),
+            |(mut min, mut max), position| {
+                for axis in 0..3 {
+                    min[axis] = min[axis].min(position[axis]);
+                    max[axis] = max[axis].max(position[axis]);
+                }
+                (min, max)
+            },
+        );
+
+        let position_view = push_view(
+            &mut bin,
+            &mut buffer_views,
+            bytemuck::cast_slice(&positions),
+            Some(ARRAY_BUFFER),
+        );
+        let normal_view = push_view(
+            &mut bin,
+            &mut buffer_views,
+            bytemuck::cast_slice(&normals),
+            Some(ARRAY_BUFFER),
+        );
+        let uv_view = push_view(
+            &mut bin,
+            &mut buffer_views,
+            bytemuck::cast_slice(&uvs),
+            Some(ARRAY_BUFFER),
+        );
+        let index_view = push_view(
+            &mut bin,
+            &mut buffer_views,
+            bytemuck::cast_slice(&indices),
+            Some(ELEMENT_ARRAY_BUFFER),
+        );
+
+        let position_accessor = accessors.len();
+        accessors.push(json!({
+            "bufferView": position_view,
+            "componentType": COMPONENT_FLOAT,
+            "count": positions.len(),
+            "type": "VEC3",
+            "min": min,
+            "max": max,
+        }));
+        accessors.push(json!({
+            "bufferView": normal_view,
+            "componentType": COMPONENT_FLOAT,
+            "count": normals.len(),
+            "type": "VEC3",
+        }));
+        accessors.push(json!({
+            "bufferView": uv_view,
+            "componentType": COMPONENT_FLOAT,
+            "count": uvs.len(),
+            "type": "VEC2",
+        }));
+        accessors.push(json!({
+            "bufferView": index_view,
+            "componentType": COMPONENT_UNSIGNED_INT,
+            "count": indices.len(),
+            "type": "SCALAR",
+        }));
+
+        let mut pbr = json!({
+            "baseColorFactor": tile.base_color,
+            "metallicFactor": tile.metallic,
+            "roughnessFactor": tile.roughness,
+        });
+        if let Some(texture) = &tile.texture {
+            let image_view = push_view(&mut bin, &mut buffer_views, texture, None);
+            // One texture per image, so both share an index
+            pbr["baseColorTexture"] = json!({ "index": images.len() });
+            images.push(json!({
+                "name": format!("{}_diffuse", tile.name),
+                "bufferView": image_view,
+                "mimeType": "image/png",
+            }));
+        }
+        materials.push(json!({
+            "name": format!("{}_material", tile.name),
+            "pbrMetallicRoughness": pbr,
+            "doubleSided": true,
+        }));
+
+        meshes.push(json!({
+            "name": tile.name,
+            "primitives": [{
+                "attributes": {
+                    "POSITION": position_accessor,
+                    "NORMAL": position_accessor + 1,
+                    "TEXCOORD_0": position_accessor + 2,
+                },
+                "indices": position_accessor + 3,
+                "material": i,
+            }],
+        }));
+
+        nodes.push(json!({
+            "name": tile.name,
+            "mesh": i,
+            "translation": tile.translation,
+        }));
+    }
+
+    let mut document = json!({
+        "asset": { "version": "2.0", "generator": "Evo-Sculptor" },
+        "scene": 0,
+        "scenes": [{ "nodes": (0..nodes.len()).collect::<Vec<_>>() }],
+        "nodes": nodes,
+        "meshes": meshes,
+        "materials": materials,
+        "accessors": accessors,
+        "bufferViews": buffer_views,
+        "buffers": [{ "byteLength": bin.len() }],
+    });
+    // glTF does not allow empty arrays
+    if !images.is_empty() {
+        document["textures"] = (0..images.len())
+            .map(|source| json!({ "source": source }))
+            .collect();
+        document["images"] = Value::Array(images);
+    }
+
+    let mut json_chunk = serde_json::to_vec(&document).expect("Failed to serialize glTF");
+    while !json_chunk.len().is_multiple_of(4) {
+        json_chunk.push(b' ');
+    }
+
+    let total_length = 12 + 8 + json_chunk.len() + 8 + bin.len();
+    let mut glb = Vec::with_capacity(total_length);
+    glb.extend_from_slice(b"glTF");
+    glb.extend_from_slice(&2u32.to_le_bytes());
+    glb.extend_from_slice(&(total_length as u32).to_le_bytes());
+
+    glb.extend_from_slice(&(json_chunk.len() as u32).to_le_bytes());
+    glb.extend_from_slice(b"JSON");
+    glb.extend_from_slice(&json_chunk);
+
+    glb.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+    glb.extend_from_slice(b"BIN\0");
+    glb.extend_from_slice(&bin);
+
+    glb
+}
+
+/// Appends `bytes` to the binary chunk, 4-byte aligned, and returns the new buffer view index.
+/// Views of image data have no `target`.
+fn push_view(
+    bin: &mut Vec<u8>,
+    buffer_views: &mut Vec<Value>,
+    bytes: &[u8],
+    target: Option<u32>,
+) -> usize {
+    let offset = bin.len();
+    bin.extend_from_slice(bytes);
+    while !bin.len().is_multiple_of(4) {
+        bin.push(0);
+    }
+
+    let mut view = json!({
+        "buffer": 0,
+        "byteOffset": offset,
+        "byteLength": bytes.len(),
+    });
+    if let Some(target) = target {
+        view["target"] = json!(target);
+    }
+    buffer_views.push(view);
+    buffer_views.len() - 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::field::SculptField;
+    use crate::state::{SculptFlags, StitchingType};
+
+    fn tile(name: &str, stitching_type: StitchingType, texture: Option<Vec<u8>>) -> GltfTile {
+        let (width, height) = (5, 4);
+        let samples = (0..width * height)
+            .map(|i| {
+                let (x, y) = ((i % width) as f32, (i / width) as f32);
+                [x / width as f32, y / height as f32, (x * y) / 20.0]
+            })
+            .collect();
+        let field = SculptField::new(width, height, samples);
+        GltfTile {
+            name: name.to_string(),
+            data: sculpt::create_sculpt_mesh(&field, 5.0, stitching_type, SculptFlags::default()),
+            translation: [1.0, 0.0, -1.0],
+            base_color: [1.0; 4],
+            texture,
+            metallic: 0.2,
+            roughness: 0.6,
+        }
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> usize {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize
+    }
+
+    /// Splits a GLB into its JSON document and binary chunk, checking the framing.
+    fn read_glb(glb: &[u8]) -> (Value, &[u8]) {
+        assert_eq!(&glb[..4], b"glTF");
+        assert_eq!(u32_at(glb, 4), 2);
+        assert_eq!(u32_at(glb, 8), glb.len());
+
+        let json_length = u32_at(glb, 12);
+        assert_eq!(&glb[16..20], b"JSON");
+        assert!(json_length.is_multiple_of(4));
+        let document = serde_json::from_slice(&glb[20..20 + json_length]).unwrap();
+
+        let bin_start = 20 + json_length;
+        let bin_length = u32_at(glb, bin_start);
+        assert_eq!(&glb[bin_start + 4..bin_start + 8], b"BIN\0");
+        assert!(bin_length.is_multiple_of(4));
+        assert_eq!(bin_start + 8 + bin_length, glb.len());
+        (document, &glb[bin_start + 8..])
+    }
+
+    #[test]
+    fn glb_chunks_and_views_are_aligned() {
+        // Five bytes of "PNG" to push the next view off alignment
+        let tiles = [
+            tile(
+                "a",
+                StitchingType::Torus,
+                Some(vec![0x89, b'P', b'N', b'G', 0]),
+            ),
+            tile("b", StitchingType::Sphere, None),
+        ];
+        let glb = write_glb(&tiles);
+        let (document, bin) = read_glb(&glb);
+
+        assert_eq!(document["buffers"][0]["byteLength"], bin.len());
+        for view in document["bufferViews"].as_array().unwrap() {
+            let offset = view["byteOffset"].as_u64().unwrap() as usize;
+            let length = view["byteLength"].as_u64().unwrap() as usize;
+            assert!(offset.is_multiple_of(4));
+            assert!(offset + length <= bin.len());
+        }
+        assert_eq!(document["images"].as_array().unwrap().len(), 1);
+        assert_eq!(document["textures"][0]["source"], 0);
+        assert_eq!(
+            document["materials"][0]["pbrMetallicRoughness"]["baseColorTexture"]["index"],
+            0
+        );
+        assert!(document["materials"][1]["pbrMetallicRoughness"]["baseColorTexture"].is_null());
+    }
+
+    #[test]
+    fn glb_accessors_match_the_meshes() {
+        let tiles = [
+            tile("a", StitchingType::Cylinder, None),
+            tile("b", StitchingType::Plane, None),
+        ];
+        let glb = write_glb(&tiles);
+        let (document, bin) = read_glb(&glb);
+        let accessors = document["accessors"].as_array().unwrap();
+        let views = document["bufferViews"].as_array().unwrap();
+        assert!(document.get("images").is_none());
+
+        for (i, tile) in tiles.iter().enumerate() {
+            let mesh = sculpt::unweld_uv_seams(&tile.data);
+            let primitive = &document["meshes"][i]["primitives"][0];
+            let accessor = |attribute: &Value| &accessors[attribute.as_u64().unwrap() as usize];
+            let count = |attribute: &Value| accessor(attribute)["count"].as_u64().unwrap() as usize;
+
+            let attributes = &primitive["attributes"];
+            assert_eq!(count(&attributes["POSITION"]), mesh.positions.len());
+            assert_eq!(count(&attributes["NORMAL"]), mesh.positions.len());
+            assert_eq!(count(&attributes["TEXCOORD_0"]), mesh.positions.len());
+            assert_eq!(count(&primitive["indices"]), mesh.indices.len());
+            assert!(count(&primitive["indices"]).is_multiple_of(3));
+
+            // Every index stored in the binary chunk names a vertex
+            let view = &views[accessor(&primitive["indices"])["bufferView"]
+                .as_u64()
+                .unwrap() as usize];
+            let offset = view["byteOffset"].as_u64().unwrap() as usize;
+            let length = view["byteLength"].as_u64().unwrap() as usize;
+            assert_eq!(length, mesh.indices.len() * 4);
+            let stored: Vec<u32> = bin[offset..offset + length]
+                .chunks_exact(4)
+                .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+                .collect();
+            assert_eq!(stored, mesh.indices);
+            assert!(
+                stored
+                    .iter()
+                    .all(|&index| (index as usize) < mesh.positions.len())
+            );
+        }
+    }
+}
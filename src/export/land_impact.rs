@@ -0,0 +1,142 @@
+use bevy::math::Vec3;
+use std::f32::consts::PI;
+
+use crate::field::SculptField;
+use crate::sculpt::{self, SculptMeshData};
+use crate::state::{SculptFlags, StitchingType};
+
+/// Triangle budget the viewer spreads 15000 streaming cost units over.
+const MESH_TRIANGLE_BUDGET: f32 = 250_000.0;
+
+/// Area of the circle enclosing a region, the viewer's upper bound for LOD areas.
+const MAX_AREA: f32 = 102_944.0;
+
+/// Map divisors of the lowest to high LOD of a mesh upload, as written by the
+/// COLLADA exporter.
+const MESH_LOD_DIVISORS: [usize; 4] = [8, 4, 2, 1];
+
+/// Real-world size and upload cost of a sculpt at a given prim scale.
+#[derive(Debug, Clone)]
+pub struct SizeReport {
+    pub index: usize,
+    pub scale: f32,
+    /// Extent of the sculpted surface along X, Y and Z, in metres.
+    pub bounds: [f32; 3],
+    /// Triangles the SL viewer draws for the sculpt, LOD 0 (lowest) to 3.
+    pub sculpt_triangles: [usize; 4],
+    /// Triangles of the same shape uploaded as a mesh with generated LODs, lowest first.
+    pub mesh_triangles: [usize; 4],
+    /// Download weight of that mesh, which decides its land impact.
+    pub streaming_cost: f32,
+}
+
+impl SizeReport {
+    /// Land impact of the mesh upload, never below one.
+    pub fn land_impact(&self) -> u32 {
+        self.streaming_cost.ceil().max(1.0) as u32
+    }
+}
+
+/// Measures the sculpt field of tile `index` at `scale` metres.
+pub fn estimate_size(
+    field: &SculptField,
+    stitching_type: StitchingType,
+    flags: SculptFlags,
+    scale: f32,
+    index: usize,
+) -> SizeReport {
+    let full = sculpt::create_sculpt_mesh(field, scale, stitching_type, flags);
+
+    let sculpt_triangles = std::array::from_fn(|lod| {
+        count_triangles(&sculpt::create_sl_sculpt_mesh(
+            field,
+            scale,
+            stitching_type,
+            flags,
+            lod,
+        ))
+    });
+
+    let mesh_triangles = MESH_LOD_DIVISORS.map(|divisor| {
+        if divisor == 1 {
+            return count_triangles(&full);
+        }
+        let width = (field.width() / divisor).max(2);
+        let height = (field.height() / divisor).max(2);
+        let lod_field = sculpt::downsample_field(field, width, height, stitching_type);
+        count_triangles(&sculpt::create_sculpt_mesh(
+            &lod_field,
+            scale,
+            stitching_type,
+            flags,
+        ))
+    });
+
+    let bounds = mesh_bounds(&full);
+    // The prim box, not the surface inside it, is what the viewer measures
+    let radius = 0.5 * (3.0 * scale * scale).sqrt();
+
+    SizeReport {
+        index,
+        scale,
+        bounds,
+        sculpt_triangles,
+        mesh_triangles,
+        streaming_cost: streaming_cost(radius, mesh_triangles),
+    }
+}
+
+/// The viewer's mesh streaming cost: triangles of each LOD weighted by the
+/// area of the region it is seen from, with LODs switching at fixed ratios
+/// of the object radius.
+fn streaming_cost(radius: f32, triangles: [usize; 4]) -> f32 {
+    let max_distance = 512.0;
+    let dlowest = (radius / 0.03).min(max_distance);
+    let dlow = (radius / 0.06).min(max_distance);
+    let dmid = (radius / 0.24).min(max_distance);
+
+    let high_area = (PI * dmid * dmid).min(MAX_AREA);
+    let mid_area = (PI * dlow * dlow).min(MAX_AREA);
+    let low_area = (PI * dlowest * dlowest).min(MAX_AREA);
+
+    let areas = [
+        (MAX_AREA - low_area).clamp(1.0, MAX_AREA),
+        (low_area - mid_area).clamp(1.0, MAX_AREA),
+        (mid_area - high_area).clamp(1.0, MAX_AREA),
+        high_area.clamp(1.0, MAX_AREA),
+    ];
+    let total_area: f32 = areas.iter().sum();
+
+    let weighted_average: f32 = areas
+        .iter()
+        .zip(triangles)
+        .map(|(area, count)| area / total_area * count as f32)
+        .sum();
+
+    weighted_average / MESH_TRIANGLE_BUDGET * 15_000.0
+}
+
+/// Triangles with any surface. Collapsed ones are never drawn, and neither are
+/// those the SL meshes squeeze into a pole from separate vertices.
+fn count_triangles(data: &SculptMeshData) -> usize {
+    let corners: Vec<usize> = data.indices.iter().collect();
+    corners
+        .chunks_exact(3)
+        .filter(|triangle| !sculpt::is_collapsed_triangle(triangle))
+        .filter(|triangle| {
+            let [a, b, c] = [0, 1, 2].map(|corner| Vec3::from(data.vertices[triangle[corner]]));
+            (b - a).cross(c - a) != Vec3::ZERO
+        })
+        .count()
+}
+
+fn mesh_bounds(data: &SculptMeshData) -> [f32; 3] {
+    let (min, max) = data
+        .vertices
+        .iter()
+        .map(|&vertex| Vec3::from(vertex))
+        .fold((Vec3::MAX, Vec3::MIN), |(min, max), vertex| {
+            (min.min(vertex), max.max(vertex))
+        });
+    (max - min).max(Vec3::ZERO).to_array()
+}
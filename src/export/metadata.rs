@@ -0,0 +1,81 @@
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+use crate::generator::{Genome, ImageSettings};
+use crate::session;
+use crate::state::{
+    InputMapping, InputSet, Normalization, SculptFlags, SeamTreatment, StitchingType,
+};
+
+/// Provenance written next to an exported sculpt map.
+#[derive(Serialize)]
+pub struct SculptMetadata {
+    pub generator: &'static str,
+    pub generation: u64,
+    pub grid_index: usize,
+    pub stitching_type: StitchingType,
+    pub sculpt_flags: SculptFlags,
+    pub seam_treatment: SeamTreatment,
+    pub inputs: InputSet,
+    pub input_mapping: InputMapping,
+    pub normalization: Normalization,
+    pub genome_hash: String,
+    pub network: NetworkSize,
+    pub activations: Vec<String>,
+    pub resolution: [usize; 2],
+}
+
+#[derive(Serialize)]
+pub struct NetworkSize {
+    pub inputs: usize,
+    pub hidden: usize,
+    pub outputs: usize,
+}
+
+impl SculptMetadata {
+    pub fn new(
+        genome: &Genome,
+        generation: u64,
+        grid_index: usize,
+        settings: ImageSettings,
+        sculpt_flags: SculptFlags,
+        resolution: [usize; 2],
+    ) -> Self {
+        // The debug format for ActivationFn includes a newline, so we trim it.
+        let activations: BTreeSet<String> = genome
+            .input_layer
+            .iter()
+            .chain(&genome.hidden_layers)
+            .chain(&genome.output_layer)
+            .map(|neuron| {
+                format!("{:?}", neuron.read().unwrap().activation)
+                    .trim()
+                    .to_string()
+            })
+            .collect();
+
+        Self {
+            generator: "Evo-Sculptor",
+            generation,
+            grid_index,
+            stitching_type: settings.stitching_type,
+            sculpt_flags,
+            seam_treatment: settings.seam_treatment,
+            inputs: settings.inputs,
+            input_mapping: settings.input_mapping,
+            normalization: settings.normalization,
+            genome_hash: format!("{:016x}", session::genome_hash(genome)),
+            network: NetworkSize {
+                inputs: genome.input_layer.len(),
+                hidden: genome.hidden_layers.len(),
+                outputs: genome.output_layer.len(),
+            },
+            activations: activations.into_iter().collect(),
+            resolution,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec_pretty(self).expect("Failed to serialize metadata")
+    }
+}
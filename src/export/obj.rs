@@ -0,0 +1,177 @@
+use std::fmt::Write;
+
+use crate::sculpt::{self, SculptMeshData};
+
+/// Writes a sculpt mesh as a Wavefront OBJ with positions, smooth normals
+/// and UVs laid out for its stitching type.
+pub fn write_obj(data: &SculptMeshData, object_name: &str) -> Vec<u8> {
+    let normals = sculpt::compute_smooth_normals(data);
+    let (uvs, uv_indices) = sculpt::compute_sculpt_uvs(data);
+    let corners: Vec<usize> = data.indices.iter().collect();
+
+    let mut obj = String::new();
+    writeln!(obj, "# Evo-Sculptor sculpt mesh").unwrap();
+    writeln!(
+        obj,
+        "# {}x{} grid, {:?} stitching",
+        data.width, data.height, data.stitching_type
+    )
+    .unwrap();
+    writeln!(obj, "o {}", object_name).unwrap();
+
+    for [x, y, z] in &data.vertices {
+        writeln!(obj, "v {} {} {}", x, y, z).unwrap();
+    }
+    for [u, v] in &uvs {
+        writeln!(obj, "vt {} {}", u, v).unwrap();
+    }
+    for [x, y, z] in &normals {
+        writeln!(obj, "vn {} {} {}", x, y, z).unwrap();
+    }
+
+    writeln!(obj, "s 1").unwrap();
+    for (triangle, uv_triangle) in corners.chunks_exact(3).zip(uv_indices.chunks_exact(3)) {
+        if sculpt::is_collapsed_triangle(triangle) {
+            continue;
+        }
+        // OBJ indices are 1-based
+        writeln!(
+            obj,
+            "f {}/{}/{} {}/{}/{} {}/{}/{}",
+            triangle[0] + 1,
+            uv_triangle[0] + 1,
+            triangle[0] + 1,
+            triangle[1] + 1,
+            uv_triangle[1] + 1,
+            triangle[1] + 1,
+            triangle[2] + 1,
+            uv_triangle[2] + 1,
+            triangle[2] + 1,
+        )
+        .unwrap();
+    }
+
+    obj.into_bytes()
+}
+
+/// Triangulated geometry read from an OBJ file.
+#[derive(Debug)]
+pub struct ObjMesh {
+    pub positions: Vec<[f32; 3]>,
+    pub triangles: Vec<[usize; 3]>,
+}
+
+/// Reads the positions and faces of a Wavefront OBJ, ignoring everything
+/// else. Polygons are split into triangle fans.
+pub fn read_obj(data: &[u8]) -> Result<ObjMesh, String> {
+    let text = std::str::from_utf8(data).map_err(|e| format!("OBJ is not text: {}", e))?;
+    let mut positions = Vec::new();
+    let mut triangles = Vec::new();
+
+    for (line_number, line) in text.lines().enumerate() {
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("v") => {
+                let mut position = [0.0; 3];
+                for component in &mut position {
+                    *component = tokens
+                        .next()
+                        .and_then(|token| token.parse().ok())
+                        .ok_or_else(|| format!("Bad vertex on line {}", line_number + 1))?;
+                }
+                positions.push(position);
+            }
+            Some("f") => {
+                // Only the position index of "v/vt/vn" is used, negative ones count back
+                let corners = tokens
+                    .map(|token| {
+                        let index: i64 = token
+                            .split('/')
+                            .next()
+                            .and_then(|index| index.parse().ok())
+                            .ok_or_else(|| format!("Bad face on line {}", line_number + 1))?;
+                        let resolved = if index < 0 {
+                            positions.len() as i64 + index
+                        } else {
+                            index - 1
+                        };
+                        if resolved < 0 || resolved >= positions.len() as i64 {
+                            return Err(format!(
+                                "Face on line {} uses missing vertex {}",
+                                line_number + 1,
+                                index
+                            ));
+                        }
+                        Ok(resolved as usize)
+                    })
+                    .collect::<Result<Vec<usize>, String>>()?;
+                for i in 1..corners.len().saturating_sub(1) {
+                    triangles.push([corners[0], corners[i], corners[i + 1]]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if triangles.is_empty() {
+        return Err("OBJ has no faces".to_string());
+    }
+
+    Ok(ObjMesh {
+        positions,
+        triangles,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_positions_and_splits_polygons_into_fans() {
+        let obj = b"# a quad\no quad\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0.5\nvn 0 0 1\nf 1 2 3 4\n";
+        let mesh = read_obj(obj).unwrap();
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.positions[3], [0.0, 1.0, 0.5]);
+        assert_eq!(mesh.triangles, [[0, 1, 2], [0, 2, 3]]);
+    }
+
+    #[test]
+    fn uses_position_of_slashed_and_negative_indices() {
+        let obj = b"v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1/1/1 2//1 -1\n";
+        assert_eq!(read_obj(obj).unwrap().triangles, [[0, 1, 2]]);
+    }
+
+    #[test]
+    fn rejects_broken_files() {
+        assert_eq!(read_obj(b"v 0 0\n").unwrap_err(), "Bad vertex on line 1");
+        assert_eq!(
+            read_obj(b"v 0 0 0\nf 1 2 3\n").unwrap_err(),
+            "Face on line 2 uses missing vertex 2"
+        );
+        assert_eq!(
+            read_obj(b"v 0 0 0\nf 1 x 1\n").unwrap_err(),
+            "Bad face on line 2"
+        );
+        assert_eq!(read_obj(b"v 0 0 0\n").unwrap_err(), "OBJ has no faces");
+        assert!(read_obj(&[0xff, 0xfe]).is_err());
+    }
+
+    #[test]
+    fn written_mesh_reads_back() {
+        let field = crate::field::SculptField::new(
+            3,
+            2,
+            (0..6).map(|i| [i as f32 / 5.0, 0.5, 0.25]).collect(),
+        );
+        let data = sculpt::create_sculpt_mesh(
+            &field,
+            1.0,
+            crate::state::StitchingType::Plane,
+            Default::default(),
+        );
+        let mesh = read_obj(&write_obj(&data, "tile")).unwrap();
+        assert_eq!(mesh.positions, data.vertices);
+        assert_eq!(mesh.triangles.len(), data.indices.len() / 3);
+    }
+}
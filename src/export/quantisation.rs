@@ -0,0 +1,80 @@
+use bevy::math::Vec3;
+
+use crate::field::SculptField;
+
+/// Float steps smaller than this are evaluation noise rather than detail.
+const MIN_STEP: f32 = 1e-4;
+
+/// Share of flattened steps past which a tile is flagged as losing detail.
+const LOSSY_FLATTENED_STEPS: f32 = 0.05;
+
+/// How far the 8-bit sculpt map of a tile moves its vertices away from the
+/// float positions the network produced.
+#[derive(Debug, Clone)]
+pub struct QuantisationReport {
+    pub index: usize,
+    /// Root mean square vertex offset, in metres at the prim scale.
+    pub rms_error: f32,
+    /// Largest vertex offset, in metres at the prim scale.
+    pub max_error: f32,
+    /// Share of neighbouring vertices that are apart in float but land on
+    /// the same point in 8 bits, i.e. detail the export flattens away.
+    pub flattened_steps: f32,
+}
+
+impl QuantisationReport {
+    /// Whether enough steps are flattened for the export to visibly lose detail.
+    pub fn loses_detail(&self) -> bool {
+        self.flattened_steps > LOSSY_FLATTENED_STEPS
+    }
+}
+
+/// Compares the field of tile `index` with the 8-bit map it is exported as,
+/// at `scale` metres.
+pub fn measure_quantisation(field: &SculptField, scale: f32, index: usize) -> QuantisationReport {
+    let (width, height) = (field.width(), field.height());
+    let values = field.samples();
+    let quantized = field.to_rgb8();
+
+    let errors: Vec<f32> = values
+        .iter()
+        .zip(&quantized)
+        .map(|(&value, &levels)| {
+            let stored = Vec3::from(levels.map(|level| level as f32 / 255.0));
+            (Vec3::from(value) - stored).length() * scale
+        })
+        .collect();
+    let count = errors.len().max(1) as f32;
+    let rms_error = (errors.iter().map(|error| error * error).sum::<f32>() / count).sqrt();
+    let max_error = errors.iter().copied().fold(0.0, f32::max);
+
+    // Right and lower neighbours, the seams are left to the seam treatment
+    let mut steps = 0;
+    let mut flattened = 0;
+    for y in 0..height {
+        for x in 0..width {
+            let here = y * width + x;
+            let neighbours = [
+                (x + 1 < width).then_some(here + 1),
+                (y + 1 < height).then_some(here + width),
+            ];
+            for next in neighbours.into_iter().flatten() {
+                let step = Vec3::from(values[here]) - Vec3::from(values[next]);
+                if step.abs().max_element() < MIN_STEP {
+                    continue;
+                }
+                steps += 1;
+                if quantized[here] == quantized[next] {
+                    flattened += 1;
+                }
+            }
+        }
+    }
+
+    QuantisationReport {
+        index,
+        rms_error,
+        max_error,
+        flattened_steps: flattened as f32 / steps.max(1) as f32,
+    }
+}
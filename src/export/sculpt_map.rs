@@ -0,0 +1,248 @@
+use image::{
+    DynamicImage, ImageBuffer, ImageFormat, Rgb32FImage, Rgba,
+    imageops::{self, FilterType},
+};
+use std::io::Cursor;
+
+use crate::field::SculptField;
+use crate::generator::{Genome, ImageSettings};
+use crate::state::StitchingType;
+use crate::{generator, io, sculpt};
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum SculptMapFormat {
+    #[default]
+    Tga,
+    Png,
+}
+
+impl SculptMapFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            SculptMapFormat::Tga => "tga",
+            SculptMapFormat::Png => "png",
+        }
+    }
+
+    pub fn file_filter(self) -> io::FileFilter {
+        match self {
+            SculptMapFormat::Tga => io::TGA_FILTER,
+            SculptMapFormat::Png => io::PNG_FILTER,
+        }
+    }
+
+    fn image_format(self) -> ImageFormat {
+        match self {
+            SculptMapFormat::Tga => ImageFormat::Tga,
+            SculptMapFormat::Png => ImageFormat::Png,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum ResampleFilter {
+    /// No interpolation/blur, keeps the sculpt grid crisp
+    #[default]
+    Nearest,
+    Bilinear,
+    CatmullRom,
+    Lanczos3,
+}
+
+impl ResampleFilter {
+    pub const ALL: [ResampleFilter; 4] = [
+        ResampleFilter::Nearest,
+        ResampleFilter::Bilinear,
+        ResampleFilter::CatmullRom,
+        ResampleFilter::Lanczos3,
+    ];
+
+    fn filter_type(self) -> FilterType {
+        match self {
+            ResampleFilter::Nearest => FilterType::Nearest,
+            ResampleFilter::Bilinear => FilterType::Triangle,
+            ResampleFilter::CatmullRom => FilterType::CatmullRom,
+            ResampleFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+/// Sizes offered for exported sculpt maps, as the side of a square map.
+pub const EXPORT_SIZES: [usize; 3] = [32, 64, 128];
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct SculptMapSettings {
+    pub format: SculptMapFormat,
+    pub size: usize,
+    pub filter: ResampleFilter,
+    /// Evaluate the network at the export size instead of upscaling the preview.
+    pub reevaluate: bool,
+    /// Write a JSON metadata sidecar next to the sculpt map.
+    pub sidecar: bool,
+}
+
+impl Default for SculptMapSettings {
+    fn default() -> Self {
+        Self {
+            format: SculptMapFormat::default(),
+            size: 64,
+            filter: ResampleFilter::default(),
+            reevaluate: true,
+            sidecar: false,
+        }
+    }
+}
+
+/// Encodes an already rendered sculpt map.
+pub fn encode_image(sculpt_map: &DynamicImage, format: SculptMapFormat) -> Vec<u8> {
+    // 3. Write the image to the byte vector
+    let mut bytes: Vec<u8> = Vec::new();
+    sculpt_map
+        .write_to(&mut Cursor::new(&mut bytes), format.image_format())
+        .expect("Failed to encode sculpt map");
+
+    bytes
+}
+
+/// Generates the sculpt map of `topology` exactly as it will be exported.
+pub fn render_sculpt_map(
+    topology: &Genome,
+    settings: &SculptMapSettings,
+    image_settings: ImageSettings,
+) -> DynamicImage {
+    let [width, height] = image_settings.aspect.dimensions(settings.size);
+
+    // 1. Generate the field, at full size if the preview would have to be upscaled
+    let field = if settings.reevaluate && settings.size > generator::PREVIEW_RESOLUTION {
+        generator::generate_field(topology, width, height, image_settings)
+    } else {
+        generator::generate_field_from_topology(
+            topology,
+            image_settings,
+            generator::PREVIEW_RESOLUTION,
+        )
+    };
+
+    // 2. Resample whatever is left to the export size, still in float
+    let field = if field.width() == width && field.height() == height {
+        field
+    } else {
+        resize_field(&field, width, height, settings.filter)
+    };
+
+    // 3. Only now round it to 8 bits
+    field_to_dynamic(&field)
+}
+
+/// Resamples a field with the `image` crate's filters, keeping float precision.
+fn resize_field(
+    field: &SculptField,
+    width: usize,
+    height: usize,
+    filter: ResampleFilter,
+) -> SculptField {
+    let raw = field.samples().iter().flatten().copied().collect();
+    let buffer = Rgb32FImage::from_raw(field.width() as u32, field.height() as u32, raw)
+        .expect("SculptField size does not match its samples");
+    let resized = imageops::resize(&buffer, width as u32, height as u32, filter.filter_type());
+    let samples = resized.pixels().map(|pixel| pixel.0).collect();
+    SculptField::new(width, height, samples)
+}
+
+/// Stores a field as the opaque 8-bit pixels of an `image` crate image.
+pub fn field_to_dynamic(field: &SculptField) -> DynamicImage {
+    let raw: Vec<u8> = field
+        .to_rgb8()
+        .into_iter()
+        .flat_map(|[r, g, b]| [r, g, b, 255])
+        .collect();
+
+    let buffer =
+        ImageBuffer::<Rgba<u8>, _>::from_raw(field.width() as u32, field.height() as u32, raw)
+            .expect("SculptField size does not match its samples");
+    DynamicImage::ImageRgba8(buffer)
+}
+
+/// Reads a field back out of an `image` crate image.
+/// Alpha carries no geometry, and premultiplying it would shift the positions.
+pub fn dynamic_to_field(image: &DynamicImage) -> SculptField {
+    let rgb = image.to_rgb8();
+    SculptField::from_rgb8(rgb.width() as usize, rgb.height() as usize, rgb.as_raw())
+}
+
+/// Most pixels kept when importing, bigger maps are sampled down to fit.
+const MAX_IMPORT_PIXELS: usize = 128 * 128;
+
+/// Reads a TGA or PNG sculpt map.
+pub fn decode_sculpt_map(data: &[u8]) -> Result<SculptField, String> {
+    // TGA has no signature to recognise it by, so it is what is left
+    let decoded = image::load_from_memory(data)
+        .or_else(|_| image::load_from_memory_with_format(data, ImageFormat::Tga))
+        .map_err(|e| format!("Unreadable image: {}", e))?;
+    let field = dynamic_to_field(&decoded);
+    let (width, height) = (field.width(), field.height());
+    if width < 2 || height < 2 {
+        return Err(format!("Sculpt map is only {}x{}", width, height));
+    }
+
+    // Halve both sides together so oblong maps keep their aspect
+    let (mut target_width, mut target_height) = (width, height);
+    while target_width * target_height > MAX_IMPORT_PIXELS {
+        target_width = (target_width / 2).max(2);
+        target_height = (target_height / 2).max(2);
+    }
+    if (target_width, target_height) != (width, height) {
+        return Ok(sculpt::downsample_field(
+            &field,
+            target_width,
+            target_height,
+            StitchingType::Plane,
+        ));
+    }
+
+    Ok(field)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field() -> SculptField {
+        let samples = (0..16 * 8)
+            .map(|i| {
+                let (x, y) = ((i % 16) as f32 / 15.0, (i / 16) as f32 / 7.0);
+                [x, y, (x * y).sqrt()]
+            })
+            .collect();
+        SculptField::new(16, 8, samples)
+    }
+
+    #[test]
+    fn encoded_maps_decode_to_their_8_bit_field() {
+        let field = field();
+        for format in [SculptMapFormat::Png, SculptMapFormat::Tga] {
+            let bytes = encode_image(&field_to_dynamic(&field), format);
+            assert_eq!(decode_sculpt_map(&bytes).unwrap(), field.quantized());
+        }
+    }
+
+    #[test]
+    fn alpha_does_not_move_the_geometry() {
+        let field = field();
+        let mut image = field_to_dynamic(&field).to_rgba8();
+        image.pixels_mut().for_each(|pixel| pixel[3] = 0);
+        assert_eq!(
+            dynamic_to_field(&DynamicImage::ImageRgba8(image)),
+            field.quantized()
+        );
+    }
+
+    #[test]
+    fn large_maps_are_sampled_down_on_import() {
+        let big = SculptField::new(256, 128, vec![[0.5; 3]; 256 * 128]);
+        let bytes = encode_image(&field_to_dynamic(&big), SculptMapFormat::Png);
+        let imported = decode_sculpt_map(&bytes).unwrap();
+        assert_eq!((imported.width(), imported.height()), (128, 64));
+        assert!(decode_sculpt_map(b"not an image").is_err());
+    }
+}
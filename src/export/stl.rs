@@ -0,0 +1,227 @@
+use bevy::math::Vec3;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::sculpt::{self, SculptMeshData};
+use crate::state::StitchingType;
+
+/// Printability summary of a sculpt mesh.
+#[derive(Debug, Clone)]
+pub struct MeshReport {
+    pub index: usize,
+    pub stitching_type: StitchingType,
+    pub triangles: usize,
+    /// Triangles with (almost) no area.
+    pub degenerate_triangles: usize,
+    /// Edges used by a single triangle, i.e. holes in the surface.
+    pub open_edges: usize,
+    /// Edges shared by more than two triangles.
+    pub non_manifold_edges: usize,
+}
+
+impl MeshReport {
+    pub fn is_closed(&self) -> bool {
+        self.open_edges == 0
+    }
+
+    pub fn is_manifold(&self) -> bool {
+        self.non_manifold_edges == 0
+    }
+
+    /// Whether the stitching type is meant to produce a closed surface.
+    pub fn expects_closed(&self) -> bool {
+        matches!(
+            self.stitching_type,
+            StitchingType::Sphere | StitchingType::Torus
+        )
+    }
+}
+
+/// Counts open, non-manifold and degenerate geometry of the sculpt mesh of
+/// tile `index`, as it is written to the STL.
+pub fn analyze_mesh(data: &SculptMeshData, index: usize) -> MeshReport {
+    const AREA_EPSILON: f32 = 1e-10;
+
+    let triangles = printable_triangles(data);
+    let mut edge_uses: HashMap<(usize, usize), usize> = HashMap::new();
+    let mut degenerate_triangles = 0;
+
+    for triangle in &triangles {
+        if facet_normal(data, triangle).length_squared() < AREA_EPSILON {
+            degenerate_triangles += 1;
+        }
+        for (a, b) in [
+            (triangle[0], triangle[1]),
+            (triangle[1], triangle[2]),
+            (triangle[2], triangle[0]),
+        ] {
+            *edge_uses.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+        }
+    }
+
+    MeshReport {
+        index,
+        stitching_type: data.stitching_type,
+        triangles: triangles.len(),
+        degenerate_triangles,
+        open_edges: edge_uses.values().filter(|&&uses| uses == 1).count(),
+        non_manifold_edges: edge_uses.values().filter(|&&uses| uses > 2).count(),
+    }
+}
+
+/// Writes the sculpt mesh as an ASCII STL.
+pub fn write_ascii_stl(data: &SculptMeshData, solid_name: &str) -> Vec<u8> {
+    let mut stl = String::new();
+    writeln!(stl, "solid {}", solid_name).unwrap();
+
+    for triangle in printable_triangles(data) {
+        let normal = facet_normal(data, &triangle).normalize_or_zero();
+        writeln!(stl, "  facet normal {} {} {}", normal.x, normal.y, normal.z).unwrap();
+        writeln!(stl, "    outer loop").unwrap();
+        for &vertex in &triangle {
+            let [x, y, z] = data.vertices[vertex];
+            writeln!(stl, "      vertex {} {} {}", x, y, z).unwrap();
+        }
+        writeln!(stl, "    endloop").unwrap();
+        writeln!(stl, "  endfacet").unwrap();
+    }
+
+    writeln!(stl, "endsolid {}", solid_name).unwrap();
+    stl.into_bytes()
+}
+
+/// Writes the sculpt mesh as a binary STL.
+pub fn write_binary_stl(data: &SculptMeshData, solid_name: &str) -> Vec<u8> {
+    let triangles = printable_triangles(data);
+    let mut stl = Vec::with_capacity(84 + triangles.len() * 50);
+
+    // 80 byte header, must not start with "solid"
+    let mut header = [0u8; 80];
+    let title = format!("Evo-Sculptor {}", solid_name);
+    let length = title.len().min(header.len());
+    header[..length].copy_from_slice(&title.as_bytes()[..length]);
+    stl.extend_from_slice(&header);
+    stl.extend_from_slice(&(triangles.len() as u32).to_le_bytes());
+
+    for triangle in &triangles {
+        let normal = facet_normal(data, triangle).normalize_or_zero();
+        for value in normal.to_array() {
+            stl.extend_from_slice(&value.to_le_bytes());
+        }
+        for &vertex in triangle {
+            for value in data.vertices[vertex] {
+                stl.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        // Attribute byte count
+        stl.extend_from_slice(&0u16.to_le_bytes());
+    }
+
+    stl
+}
+
+/// Triangles worth writing: the collapsed ones carry no surface.
+fn printable_triangles(data: &SculptMeshData) -> Vec<[usize; 3]> {
+    let corners: Vec<usize> = data.indices.iter().collect();
+    corners
+        .chunks_exact(3)
+        .filter(|triangle| !sculpt::is_collapsed_triangle(triangle))
+        .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+        .collect()
+}
+
+/// Unnormalized facet normal, its length is twice the triangle area.
+fn facet_normal(data: &SculptMeshData, triangle: &[usize]) -> Vec3 {
+    let a = Vec3::from(data.vertices[triangle[0]]);
+    let b = Vec3::from(data.vertices[triangle[1]]);
+    let c = Vec3::from(data.vertices[triangle[2]]);
+    (b - a).cross(c - a)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::field::SculptField;
+    use crate::state::SculptFlags;
+    use bevy::mesh::Indices;
+
+    fn mesh(vertices: Vec<[f32; 3]>, indices: Vec<u32>) -> SculptMeshData {
+        SculptMeshData {
+            vertices,
+            indices: Indices::U32(indices),
+            width: 0,
+            height: 0,
+            stitching_type: StitchingType::Plane,
+        }
+    }
+
+    fn tetrahedron() -> Vec<[f32; 3]> {
+        vec![
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, 0.0, 1.0],
+        ]
+    }
+
+    #[test]
+    fn closed_tetrahedron_has_no_open_edges() {
+        let report = analyze_mesh(
+            &mesh(tetrahedron(), vec![0, 2, 1, 0, 1, 3, 0, 3, 2, 1, 2, 3]),
+            0,
+        );
+        assert_eq!(report.triangles, 4);
+        assert_eq!(report.open_edges, 0);
+        assert_eq!(report.non_manifold_edges, 0);
+        assert_eq!(report.degenerate_triangles, 0);
+    }
+
+    #[test]
+    fn missing_face_leaves_open_edges() {
+        let report = analyze_mesh(&mesh(tetrahedron(), vec![0, 2, 1, 0, 1, 3, 0, 3, 2]), 0);
+        assert_eq!(report.open_edges, 3);
+        assert!(!report.is_closed());
+    }
+
+    #[test]
+    fn edge_shared_by_three_triangles_is_non_manifold() {
+        let mut vertices = tetrahedron();
+        vertices.push([1.0, 1.0, 1.0]);
+        let report = analyze_mesh(&mesh(vertices, vec![0, 1, 2, 0, 1, 3, 0, 1, 4]), 0);
+        assert_eq!(report.non_manifold_edges, 1);
+    }
+
+    #[test]
+    fn flat_triangles_are_degenerate_and_collapsed_ones_dropped() {
+        let vertices = vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [2.0, 0.0, 0.0]];
+        // One triangle reuses a vertex and is not written, the other has three collinear corners
+        let report = analyze_mesh(&mesh(vertices, vec![0, 0, 1, 0, 1, 2]), 0);
+        assert_eq!(report.triangles, 1);
+        assert_eq!(report.degenerate_triangles, 1);
+        assert_eq!(report.open_edges, 3);
+    }
+
+    #[test]
+    fn sphere_map_is_watertight() {
+        let (width, height) = (16, 9);
+        let samples = (0..width * height)
+            .map(|i| {
+                let u = (i % width) as f32 / width as f32 * std::f32::consts::TAU;
+                let v = (i / width) as f32 / (height - 1) as f32 * std::f32::consts::PI;
+                let point = Vec3::new(v.sin() * u.cos(), v.sin() * u.sin(), -v.cos());
+                (point * 0.5 + 0.5).to_array()
+            })
+            .collect();
+        let field = SculptField::new(width, height, samples);
+        let data =
+            sculpt::create_sculpt_mesh(&field, 1.0, StitchingType::Sphere, SculptFlags::default());
+        let report = analyze_mesh(&data, 0);
+
+        assert_eq!(report.triangles, printable_triangles(&data).len());
+        assert_eq!(report.triangles, 2 * width * (height - 2));
+        assert_eq!(report.degenerate_triangles, 0);
+        assert_eq!(report.open_edges, 0);
+        assert_eq!(report.non_manifold_edges, 0);
+        assert!(report.is_closed() && report.expects_closed());
+    }
+}
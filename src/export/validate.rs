@@ -0,0 +1,279 @@
+use image::{DynamicImage, Rgb, RgbImage};
+
+use crate::state::StitchingType;
+
+/// Largest map SL keeps lossless on upload, anything bigger is compressed
+/// and the vertices drift.
+const MAX_LOSSLESS_PIXELS: u32 = 128 * 128;
+
+/// Colour levels two pixels may differ by and still count as the same point.
+const SAME_POINT_LEVELS: u8 = 1;
+
+/// How much bigger than an ordinary column (or row) step a seam may be.
+const SEAM_TOLERANCE: f32 = 4.0;
+
+/// Upload problems found in the sculpt map of tile `index`.
+#[derive(Debug, Clone)]
+pub struct MapReport {
+    pub index: usize,
+    pub width: u32,
+    pub height: u32,
+    pub problems: Vec<String>,
+    /// The map that was checked, so exporting it anyway saves exactly this.
+    pub image: DynamicImage,
+}
+
+impl MapReport {
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Checks a sculpt map, as it will be exported, for what breaks SL uploads.
+pub fn validate_sculpt_map(
+    image: DynamicImage,
+    stitching_type: StitchingType,
+    index: usize,
+) -> MapReport {
+    let rgb = image.to_rgb8();
+    let (width, height) = rgb.dimensions();
+    let mut problems = Vec::new();
+
+    if !width.is_power_of_two() || !height.is_power_of_two() {
+        problems.push(format!(
+            "{}x{} is not a power of two, SL will rescale it",
+            width, height
+        ));
+    }
+    if width * height > MAX_LOSSLESS_PIXELS {
+        problems.push(format!(
+            "{}x{} is over 128x128 pixels, SL will not keep it lossless",
+            width, height
+        ));
+    }
+
+    for (channel, axis) in ["X", "Y", "Z"].iter().enumerate() {
+        let values = rgb.pixels().map(|pixel| pixel[channel]);
+        let min = values.clone().min().unwrap_or(0);
+        let max = values.max().unwrap_or(0);
+        if max - min <= SAME_POINT_LEVELS {
+            problems.push(format!(
+                "{} channel is flat, the sculpt has no depth on {}",
+                axis, axis
+            ));
+        }
+    }
+
+    check_seams(&rgb, stitching_type, &mut problems);
+    check_rows(&rgb, stitching_type, &mut problems);
+
+    MapReport {
+        index,
+        width,
+        height,
+        problems,
+        image,
+    }
+}
+
+/// Wrapped edges should meet about as smoothly as neighbouring pixels do,
+/// and sphere pole rows should hold the single point SL reads from them.
+fn check_seams(rgb: &RgbImage, stitching_type: StitchingType, problems: &mut Vec<String>) {
+    let (width, height) = rgb.dimensions();
+    let (wrap_x, wrap_y) = stitching_type.wrapped_axes();
+
+    if wrap_x {
+        let seam =
+            mean((0..height).map(|y| distance(rgb.get_pixel(0, y), rgb.get_pixel(width - 1, y))));
+        let step = mean((0..height).flat_map(|y| {
+            (1..width).map(move |x| distance(rgb.get_pixel(x - 1, y), rgb.get_pixel(x, y)))
+        }));
+        if seam > step * SEAM_TOLERANCE + SAME_POINT_LEVELS as f32 {
+            problems.push(format!(
+                "Left and right edges are {:.0} levels apart (columns: {:.0}), the {:?} seam will show",
+                seam, step, stitching_type
+            ));
+        }
+    }
+
+    if wrap_y {
+        let seam =
+            mean((0..width).map(|x| distance(rgb.get_pixel(x, 0), rgb.get_pixel(x, height - 1))));
+        let step = mean((0..width).flat_map(|x| {
+            (1..height).map(move |y| distance(rgb.get_pixel(x, y - 1), rgb.get_pixel(x, y)))
+        }));
+        if seam > step * SEAM_TOLERANCE + SAME_POINT_LEVELS as f32 {
+            problems.push(format!(
+                "Top and bottom edges are {:.0} levels apart (rows: {:.0}), the {:?} seam will show",
+                seam, step, stitching_type
+            ));
+        }
+    }
+
+    if stitching_type == StitchingType::Sphere {
+        for (y, edge) in [(0, "Top"), (height - 1, "Bottom")] {
+            let pole = rgb.get_pixel(width / 2, y);
+            let spread = (0..width)
+                .map(|x| distance(rgb.get_pixel(x, y), pole))
+                .max()
+                .unwrap_or(0);
+            if spread > SAME_POINT_LEVELS {
+                problems.push(format!(
+                    "{} row varies by {} levels, SL collapses it into its middle pixel",
+                    edge, spread
+                ));
+            }
+        }
+    }
+}
+
+/// Rows squashed into a single point, or repeating the row before them,
+/// mesh into strips with no area.
+fn check_rows(rgb: &RgbImage, stitching_type: StitchingType, problems: &mut Vec<String>) {
+    let (width, height) = rgb.dimensions();
+    // Sphere poles are meant to be points
+    let rows = if stitching_type == StitchingType::Sphere {
+        1..height - 1
+    } else {
+        0..height
+    };
+
+    let mut point_rows = 0;
+    let mut repeated_rows = 0;
+    for y in rows {
+        let first = rgb.get_pixel(0, y);
+        if (1..width).all(|x| distance(rgb.get_pixel(x, y), first) <= SAME_POINT_LEVELS) {
+            point_rows += 1;
+        } else if y > 0
+            && (0..width).all(|x| {
+                distance(rgb.get_pixel(x, y), rgb.get_pixel(x, y - 1)) <= SAME_POINT_LEVELS
+            })
+        {
+            repeated_rows += 1;
+        }
+    }
+
+    if point_rows > 0 {
+        problems.push(format!("{} rows collapse into a single point", point_rows));
+    }
+    if repeated_rows > 0 {
+        problems.push(format!(
+            "{} rows repeat the row above, leaving flat lines",
+            repeated_rows
+        ));
+    }
+}
+
+/// Largest per-channel difference between two pixels, in colour levels.
+fn distance(a: &Rgb<u8>, b: &Rgb<u8>) -> u8 {
+    (0..3).map(|c| a[c].abs_diff(b[c])).max().unwrap_or(0)
+}
+
+fn mean(values: impl Iterator<Item = u8>) -> f32 {
+    let (sum, count) = values.fold((0u32, 0u32), |(sum, count), value| {
+        (sum + value as u32, count + 1)
+    });
+    if count == 0 {
+        0.0
+    } else {
+        sum as f32 / count as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A map ramping up along both axes, smooth everywhere but its edges.
+    fn ramp(width: u32, height: u32) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| {
+            Rgb([
+                (x * 255 / (width - 1)) as u8,
+                (y * 255 / (height - 1)) as u8,
+                ((x + y) * 255 / (width + height - 2)) as u8,
+            ])
+        })
+    }
+
+    fn problems(rgb: RgbImage, stitching_type: StitchingType) -> Vec<String> {
+        validate_sculpt_map(DynamicImage::ImageRgb8(rgb), stitching_type, 0).problems
+    }
+
+    #[test]
+    fn smooth_plane_map_is_valid() {
+        let report = validate_sculpt_map(
+            DynamicImage::ImageRgb8(ramp(32, 32)),
+            StitchingType::Plane,
+            3,
+        );
+        assert!(report.is_valid(), "{:?}", report.problems);
+        assert_eq!((report.index, report.width, report.height), (3, 32, 32));
+    }
+
+    #[test]
+    fn odd_and_oversized_maps_are_reported() {
+        assert!(problems(ramp(48, 32), StitchingType::Plane)[0].contains("power of two"));
+        assert!(problems(ramp(256, 128), StitchingType::Plane)[0].contains("lossless"));
+    }
+
+    #[test]
+    fn flat_channel_is_reported() {
+        let mut rgb = ramp(32, 32);
+        rgb.pixels_mut().for_each(|pixel| pixel[2] = 128);
+        assert_eq!(
+            problems(rgb, StitchingType::Plane),
+            ["Z channel is flat, the sculpt has no depth on Z"]
+        );
+    }
+
+    #[test]
+    fn open_seam_is_reported_on_wrapped_axes_only() {
+        // The ramp jumps from 255 back to 0 across the left and right edges
+        let seams = problems(ramp(32, 32), StitchingType::Cylinder);
+        assert_eq!(seams.len(), 1);
+        assert!(seams[0].starts_with("Left and right edges"));
+
+        let seams = problems(ramp(32, 32), StitchingType::Torus);
+        assert_eq!(seams.len(), 2);
+        assert!(seams[1].starts_with("Top and bottom edges"));
+    }
+
+    #[test]
+    fn sphere_poles_must_be_points() {
+        let mut rgb = RgbImage::from_fn(32, 32, |x, y| {
+            let around = (x as f32 / 32.0 * std::f32::consts::TAU).sin();
+            Rgb([
+                (128.0 + around * 100.0) as u8,
+                (y * 8) as u8,
+                128 + (y % 2) as u8 * 4,
+            ])
+        });
+        for x in 0..32 {
+            rgb.put_pixel(x, 0, Rgb([128, 0, 128]));
+            rgb.put_pixel(x, 31, Rgb([128, 248, 128]));
+        }
+        assert!(problems(rgb.clone(), StitchingType::Sphere).is_empty());
+
+        rgb.put_pixel(0, 0, Rgb([0, 0, 128]));
+        assert_eq!(
+            problems(rgb, StitchingType::Sphere),
+            ["Top row varies by 128 levels, SL collapses it into its middle pixel"]
+        );
+    }
+
+    #[test]
+    fn point_and_repeated_rows_are_reported() {
+        let mut rgb = ramp(32, 32);
+        for x in 0..32 {
+            rgb.put_pixel(x, 5, Rgb([10, 10, 10]));
+            rgb.put_pixel(x, 9, *rgb.get_pixel(x, 8));
+        }
+        assert_eq!(
+            problems(rgb, StitchingType::Plane),
+            [
+                "1 rows collapse into a single point",
+                "1 rows repeat the row above, leaving flat lines"
+            ]
+        );
+    }
+}
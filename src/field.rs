@@ -0,0 +1,134 @@
+// src/field.rs
+
+/// A sculpt map before it is stored as an image: a `width` x `height` grid of
+/// XYZ samples normalized to [0, 1], rows top to bottom. It keeps full float
+/// precision until it is quantized for export or display.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SculptField {
+    width: usize,
+    height: usize,
+    samples: Vec<[f32; 3]>,
+}
+
+impl SculptField {
+    pub fn new(width: usize, height: usize, samples: Vec<[f32; 3]>) -> Self {
+        assert_eq!(
+            samples.len(),
+            width * height,
+            "SculptField size does not match its samples"
+        );
+        Self {
+            width,
+            height,
+            samples,
+        }
+    }
+
+    /// Reads interleaved 8-bit RGB pixels, as decoded from a sculpt map.
+    pub fn from_rgb8(width: usize, height: usize, rgb: &[u8]) -> Self {
+        let samples = rgb
+            .chunks_exact(3)
+            .map(|pixel| [pixel[0], pixel[1], pixel[2]].map(|level| level as f32 / 255.0))
+            .collect();
+        Self::new(width, height, samples)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn samples(&self) -> &[[f32; 3]] {
+        &self.samples
+    }
+
+    pub fn sample(&self, x: usize, y: usize) -> [f32; 3] {
+        self.samples[y * self.width + x]
+    }
+
+    /// The 8-bit levels every sample is stored as.
+    pub fn to_rgb8(&self) -> Vec<[u8; 3]> {
+        self.samples
+            .iter()
+            .map(|sample| sample.map(quantize))
+            .collect()
+    }
+
+    /// The field as it comes back from its 8-bit sculpt map.
+    pub fn quantized(&self) -> Self {
+        let samples = self
+            .to_rgb8()
+            .into_iter()
+            .map(|levels| levels.map(|level| level as f32 / 255.0))
+            .collect();
+        Self::new(self.width, self.height, samples)
+    }
+}
+
+/// The 8-bit level a normalized sample is stored as, the nearest one so no
+/// sample is off by more than half a level.
+pub fn quantize(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(width: usize, height: usize) -> SculptField {
+        let samples = (0..width * height)
+            .map(|i| {
+                let (x, y) = ((i % width) as f32, (i / width) as f32);
+                [x / width as f32, y / height as f32, 0.3]
+            })
+            .collect();
+        SculptField::new(width, height, samples)
+    }
+
+    #[test]
+    fn quantize_rounds_to_the_nearest_level() {
+        assert_eq!(quantize(0.0), 0);
+        assert_eq!(quantize(1.0), 255);
+        assert_eq!(quantize(0.5), 128);
+        assert_eq!(quantize(254.6 / 255.0), 255);
+        assert_eq!(quantize(-0.2), 0);
+        assert_eq!(quantize(1.7), 255);
+    }
+
+    #[test]
+    fn rgb8_round_trips_exactly() {
+        let rgb: Vec<u8> = (0..=255).chain(0..=8).collect();
+        let field = SculptField::from_rgb8(11, 8, &rgb[..11 * 8 * 3]);
+        let levels: Vec<u8> = field.to_rgb8().into_iter().flatten().collect();
+        assert_eq!(levels, rgb[..11 * 8 * 3]);
+    }
+
+    #[test]
+    fn quantized_is_within_half_a_level_and_stable() {
+        let field = gradient(7, 5);
+        let quantized = field.quantized();
+        for (sample, stored) in field.samples().iter().zip(quantized.samples()) {
+            for c in 0..3 {
+                assert!((sample[c] - stored[c]).abs() <= 0.5 / 255.0 + 1e-6);
+            }
+        }
+        assert_eq!(quantized.quantized(), quantized);
+    }
+
+    #[test]
+    fn samples_are_read_row_by_row() {
+        let field = gradient(4, 3);
+        assert_eq!((field.width(), field.height()), (4, 3));
+        assert_eq!(field.sample(3, 2), field.samples()[2 * 4 + 3]);
+        assert_eq!(field.sample(1, 2), [0.25, 2.0 / 3.0, 0.3]);
+    }
+
+    #[test]
+    #[should_panic(expected = "SculptField size does not match its samples")]
+    fn size_must_match_samples() {
+        SculptField::new(2, 2, vec![[0.0; 3]; 3]);
+    }
+}
@@ -1,81 +1,550 @@
-use bevy_egui::egui::{self, Color32};
 use neat::{NeuralNetwork, NeuralNetworkTopology};
 
-pub fn generate_image_from_topology(topology: &NeuralNetworkTopology<3, 3>) -> egui::ColorImage {
+use std::f32::consts::{PI, TAU};
+
+use crate::field::SculptField;
+use crate::state::{
+    InputMapping, InputSet, Normalization, SculptAspect, SeamTreatment, StitchingType,
+};
+
+/// Inputs every CPPN has room for: x, y, the distance from the centre (or
+/// their stand-ins on wrapped surfaces, see `pixel_position`) and each
+/// feature of `InputSet`, in that order. Features that are turned off
+/// read zero, so genomes keep their layout when the set changes.
+pub const CPPN_INPUTS: usize = 11;
+
+/// The CPPN behind every sculpt map and texture.
+pub type Genome = NeuralNetworkTopology<CPPN_INPUTS, 3>;
+
+/// Output ranges narrower than this count as flat.
+const EPSILON: f32 = 1e-6;
+
+/// Default resolution of the grid preview, and the one sculpt maps are
+/// resampled from unless they are re-evaluated, the side of a square map.
+pub const PREVIEW_RESOLUTION: usize = 32;
+
+/// Resolutions offered for the grid preview, the side of a square map.
+pub const PREVIEW_RESOLUTIONS: [usize; 5] = [16, 32, 64, 128, 256];
+
+/// Resolution tiles are shown at until they are refined, the side of a square map.
+pub const COARSE_PREVIEW_RESOLUTION: usize = 16;
+
+/// How a network is laid out over an image, shared by sculpt maps and textures.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ImageSettings {
+    pub aspect: SculptAspect,
+    /// Decides which edges of the image meet on the mesh.
+    pub stitching_type: StitchingType,
+    pub seam_treatment: SeamTreatment,
+    pub inputs: InputSet,
+    pub input_mapping: InputMapping,
+    pub normalization: Normalization,
+}
+
+/// Evaluates `topology` over a map with as many pixels as a `resolution` x
+/// `resolution` one. Meshes are exported at the preview resolution, as shown.
+pub fn generate_field_from_topology<const I: usize>(
+    topology: &NeuralNetworkTopology<I, 3>,
+    settings: ImageSettings,
+    resolution: usize,
+) -> SculptField {
+    let [width, height] = settings.aspect.dimensions(resolution);
+    generate_field(topology, width, height, settings)
+}
+
+/// Resolution of the companion textures shown on the grid, the side of a square one.
+pub const TEXTURE_PREVIEW_RESOLUTION: usize = 64;
+
+/// Resolution of exported companion textures, the side of a square one.
+pub const TEXTURE_EXPORT_RESOLUTION: usize = 256;
+
+/// Evaluates a texture CPPN over the sculpt grid at `resolution`. Its pixels
+/// line up with the sculpt map's, so it wraps the mesh along its UVs.
+pub fn generate_texture<const I: usize>(
+    topology: &NeuralNetworkTopology<I, 3>,
+    settings: ImageSettings,
+    resolution: usize,
+) -> SculptField {
+    let [width, height] = settings.aspect.dimensions(resolution);
+    generate_field(topology, width, height, settings)
+}
+
+/// Evaluates the network over a `width` x `height` grid, with the seam
+/// treatment of `settings` applied to the edges that wrap. The outputs are
+/// normalized into [0, 1] as `settings` asks for.
+pub fn generate_field<const I: usize>(
+    topology: &NeuralNetworkTopology<I, 3>,
+    width: usize,
+    height: usize,
+    settings: ImageSettings,
+) -> SculptField {
     let network = NeuralNetwork::from(topology);
-    let width = 32;
-    let height = 32;
-    let mut image = egui::ColorImage::new([width, height], vec![Color32::BLACK; width * height]);
-    const EPSILON: f32 = 1e-6;
-
-    // --- PASS 1: Collect raw f32 outputs and find min/max for each channel ---
-    let mut r_values = Vec::with_capacity(width * height);
-    let mut g_values = Vec::with_capacity(width * height);
-    let mut b_values = Vec::with_capacity(width * height);
-
-    let mut min_r = f32::MAX;
-    let mut max_r = f32::MIN;
-    let mut min_g = f32::MAX;
-    let mut max_g = f32::MIN;
-    let mut min_b = f32::MAX;
-    let mut max_b = f32::MIN;
 
+    // --- PASS 1: Collect raw f32 outputs ---
+    let mut raw_values = Vec::with_capacity(width * height);
+    let (wrap_x, wrap_y) = settings.stitching_type.wrapped_axes();
     for y in 0..height {
         for x in 0..width {
-            let norm_x = (x as f32 / (width - 1) as f32) * 2.0 - 1.0;
-            let norm_y = (y as f32 / (height - 1) as f32) * 2.0 - 1.0;
-            let dist_from_center = (norm_x.powi(2) + norm_y.powi(2)).sqrt();
+            let position = pixel_position(settings, x, y, width, height);
+            let u = x as f32 / width as f32;
+            let v = y as f32 / height as f32;
 
-            let inputs = [norm_x, norm_y, dist_from_center];
+            let inputs = network_inputs(settings, position, u, v);
 
             network.flush_state();
             let outputs = network.predict(inputs);
 
-            let r = outputs[0];
-            let g = outputs[1];
-            let b = outputs[2];
+            raw_values.push([outputs[0], outputs[1], outputs[2]]);
+        }
+    }
 
-            r_values.push(r);
-            g_values.push(g);
-            b_values.push(b);
+    // --- PASS 2: Normalize raw values ---
+    let mut values = normalize_outputs(&raw_values, settings.normalization);
 
-            min_r = min_r.min(r);
-            max_r = max_r.max(r);
-            min_g = min_g.min(g);
-            max_g = max_g.max(g);
-            min_b = min_b.min(b);
-            max_b = max_b.max(b);
-        }
+    // --- PASS 3: Blend the seams ---
+    if wrap_x {
+        blend_seam(
+            &mut values,
+            settings.seam_treatment,
+            width,
+            height,
+            1,
+            width,
+        );
+    }
+    if wrap_y {
+        blend_seam(
+            &mut values,
+            settings.seam_treatment,
+            height,
+            width,
+            width,
+            1,
+        );
     }
 
-    let range_r = max_r - min_r;
-    let range_g = max_g - min_g;
-    let range_b = max_b - min_b;
+    SculptField::new(width, height, values)
+}
 
-    // --- PASS 2: Normalize raw values and create the final image ---
-    for (i, pixel) in image.pixels.iter_mut().enumerate() {
-        let norm_r = if range_r < EPSILON {
-            0.5
-        } else {
-            (r_values[i] - min_r) / range_r
-        };
-        let norm_g = if range_g < EPSILON {
-            0.5
-        } else {
-            (g_values[i] - min_g) / range_g
-        };
-        let norm_b = if range_b < EPSILON {
+/// Maps raw network outputs into [0, 1] the way `mode` asks for.
+fn normalize_outputs(raw_values: &[[f32; 3]], mode: Normalization) -> Vec<[f32; 3]> {
+    // Share of samples clipped off each end of a channel by percentile clipping
+    const PERCENTILE_CLIP: f32 = 0.02;
+
+    let stretch = |value: f32, (low, high): (f32, f32)| {
+        if high - low < EPSILON {
             0.5
         } else {
-            (b_values[i] - min_b) / range_b
+            ((value - low) / (high - low)).clamp(0.0, 1.0)
+        }
+    };
+
+    match mode {
+        Normalization::PerChannel | Normalization::Percentile => {
+            let clip = if mode == Normalization::Percentile {
+                PERCENTILE_CLIP
+            } else {
+                0.0
+            };
+            let bounds: [(f32, f32); 3] =
+                std::array::from_fn(|c| channel_bounds(raw_values, c, clip));
+            raw_values
+                .iter()
+                .map(|value| std::array::from_fn(|c| stretch(value[c], bounds[c])))
+                .collect()
+        }
+        Normalization::Uniform => {
+            // One scale for all channels, each centred in the cube
+            let bounds: [(f32, f32); 3] =
+                std::array::from_fn(|c| channel_bounds(raw_values, c, 0.0));
+            let range = bounds
+                .iter()
+                .map(|(low, high)| high - low)
+                .fold(0.0, f32::max);
+            raw_values
+                .iter()
+                .map(|value| {
+                    std::array::from_fn(|c| {
+                        let (low, high) = bounds[c];
+                        if range < EPSILON {
+                            0.5
+                        } else {
+                            (value[c] - (low + high) / 2.0) / range + 0.5
+                        }
+                    })
+                })
+                .collect()
+        }
+        Normalization::FixedRange => raw_values
+            .iter()
+            .map(|value| value.map(|v| (v.clamp(-1.0, 1.0) + 1.0) / 2.0))
+            .collect(),
+        Normalization::Sigmoid => raw_values
+            .iter()
+            .map(|value| value.map(|v| 1.0 / (1.0 + (-v).exp())))
+            .collect(),
+    }
+}
+
+/// Lowest and highest value of channel `c`, ignoring the `clip` share of
+/// samples at each end.
+fn channel_bounds(values: &[[f32; 3]], c: usize, clip: f32) -> (f32, f32) {
+    if clip <= 0.0 {
+        return values
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(low, high), value| {
+                (low.min(value[c]), high.max(value[c]))
+            });
+    }
+
+    let mut channel: Vec<f32> = values
+        .iter()
+        .map(|value| value[c])
+        .filter(|v| !v.is_nan())
+        .collect();
+    if channel.is_empty() {
+        return (0.0, 0.0);
+    }
+    channel.sort_by(f32::total_cmp);
+    let last = channel.len() - 1;
+    let skip = (last as f32 * clip).round() as usize;
+    (channel[skip], channel[last - skip])
+}
+
+/// The inputs of a network with `I` of them at a pixel, from its `position`
+/// (see `pixel_position`) and its fraction `u`, `v` of the way across and
+/// down the map. Features that are turned off, or do not fit, read zero.
+fn network_inputs<const I: usize>(
+    settings: ImageSettings,
+    position: [f32; 3],
+    u: f32,
+    v: f32,
+) -> [f32; I] {
+    let features = input_features(settings, position, u, v);
+    std::array::from_fn(|i| match features.get(i) {
+        Some(&(true, value)) => value,
+        _ => 0.0,
+    })
+}
+
+/// Indices of the inputs a network is fed with `settings`. The others read
+/// zero, so an output connected to none of these is flat.
+pub fn live_inputs(settings: ImageSettings) -> Vec<usize> {
+    input_features(settings, [0.0; 3], 0.0, 0.0)
+        .iter()
+        .enumerate()
+        .filter(|(_, (fed, _))| *fed)
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Every input a CPPN has room for at a pixel, and whether it is fed.
+fn input_features(
+    settings: ImageSettings,
+    [x, y, third]: [f32; 3],
+    u: f32,
+    v: f32,
+) -> [(bool, f32); CPPN_INPUTS] {
+    let set = settings.inputs;
+    let (u_angle, v_angle) = (u * TAU, v * TAU);
+    // The angle around a wrapped surface jumps at its seam
+    let polar = set.polar
+        && (settings.input_mapping != InputMapping::Surface
+            || settings.stitching_type == StitchingType::Plane);
+    [
+        (true, x),
+        (true, y),
+        (true, third),
+        (polar, y.atan2(x) / PI),
+        (set.bias, 1.0),
+        (set.absolute, x.abs()),
+        (set.absolute, y.abs()),
+        (set.periodic, u_angle.sin()),
+        (set.periodic, u_angle.cos()),
+        (set.periodic, v_angle.sin()),
+        (set.periodic, v_angle.cos()),
+    ]
+}
+
+/// The x, y and third coordinate a network reads at pixel (`x`, `y`) of a
+/// `width` x `height` map, each in [-1, 1]. The third is the distance from
+/// the centre on a flat map. On a wrapped surface the three are the point
+/// the pixel lands on, with sin and cos of the angle around the wrapped axis
+/// in x and y, so the inputs are continuous across the seam and different
+/// on either side of it.
+fn pixel_position(
+    settings: ImageSettings,
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+) -> [f32; 3] {
+    let flat = |(wrap_x, wrap_y): (bool, bool)| {
+        let (x, y) = (
+            input_coordinate(x, width, wrap_x),
+            input_coordinate(y, height, wrap_y),
+        );
+        [x, y, (x.powi(2) + y.powi(2)).sqrt()]
+    };
+    let around = x as f32 / width as f32 * TAU;
+
+    match (settings.input_mapping, settings.stitching_type) {
+        (InputMapping::Flat, _) | (InputMapping::Surface, StitchingType::Plane) => {
+            flat((false, false))
+        }
+        (InputMapping::Mirrored, stitching_type) => flat(stitching_type.wrapped_axes()),
+        (InputMapping::Surface, StitchingType::Cylinder) => [
+            around.cos(),
+            around.sin(),
+            input_coordinate(y, height, false),
+        ],
+        (InputMapping::Surface, StitchingType::Sphere) => {
+            // Rows run pole to pole, so each pole row is a single point
+            let polar = y as f32 / (height - 1).max(1) as f32 * PI;
+            [
+                polar.sin() * around.cos(),
+                polar.sin() * around.sin(),
+                polar.cos(),
+            ]
+        }
+        (InputMapping::Surface, StitchingType::Torus) => {
+            // A ring twice as wide as its tube, scaled to fit [-1, 1]
+            let tube = y as f32 / height as f32 * TAU;
+            let ring = (2.0 + tube.cos()) / 3.0;
+            [ring * around.cos(), ring * around.sin(), tube.sin()]
+        }
+    }
+}
+
+/// Network input in [-1, 1] for pixel `i` of `count` along an axis. Wrapped
+/// axes run once around a cosine, so both edges of the map read the same
+/// input and the pattern meets itself across the seam, mirrored.
+fn input_coordinate(i: usize, count: usize, wrapped: bool) -> f32 {
+    if wrapped {
+        -(i as f32 / count as f32 * TAU).cos()
+    } else {
+        (i as f32 / (count - 1).max(1) as f32) * 2.0 - 1.0
+    }
+}
+
+/// Evens out the step between the first and last line of pixels across a
+/// wrapped axis. Lines are `count` long along the axis (`step` apart in
+/// `values`), and there are `lines` of them (`stride` apart).
+fn blend_seam(
+    values: &mut [[f32; 3]],
+    treatment: SeamTreatment,
+    count: usize,
+    lines: usize,
+    step: usize,
+    stride: usize,
+) {
+    // Fraction of the map each side of the seam is crossfaded over
+    const CROSSFADE_FRACTION: usize = 8;
+
+    let band = match treatment {
+        SeamTreatment::None => return,
+        // Too short to have inner neighbours on both sides of the seam
+        SeamTreatment::EdgeAverage if count < 4 => return,
+        SeamTreatment::EdgeAverage => 1,
+        SeamTreatment::Crossfade => (count / CROSSFADE_FRACTION).clamp(1, count / 2),
+    };
+
+    for line in 0..lines {
+        let index = |i: usize| line * stride + i * step;
+        if treatment == SeamTreatment::EdgeAverage {
+            // Space both edge lines evenly between their inner neighbours, so
+            // the seam takes a step like any other instead of none at all
+            let (before, after) = (values[index(count - 2)], values[index(1)]);
+            let between = |t: f32| std::array::from_fn(|c| before[c] + (after[c] - before[c]) * t);
+            values[index(count - 1)] = between(1.0 / 3.0);
+            values[index(0)] = between(2.0 / 3.0);
+            continue;
+        }
+        for k in 0..band {
+            let near = index(k);
+            let far = index(count - 1 - k);
+            // Ease towards the middle, stopping short so the seam keeps a step
+            let weight = 0.5 * (1.0 - (2 * k + 1) as f32 / (2 * band) as f32);
+            let (a, b) = (values[near], values[far]);
+            values[near] = std::array::from_fn(|c| a[c] + (b[c] - a[c]) * weight);
+            values[far] = std::array::from_fn(|c| b[c] + (a[c] - b[c]) * weight);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(stitching_type: StitchingType, inputs: InputSet) -> ImageSettings {
+        ImageSettings {
+            aspect: SculptAspect::default(),
+            stitching_type,
+            seam_treatment: SeamTreatment::default(),
+            inputs,
+            input_mapping: InputMapping::default(),
+            normalization: Normalization::default(),
+        }
+    }
+
+    #[test]
+    fn live_inputs_follow_the_input_set() {
+        let plane = |inputs| live_inputs(settings(StitchingType::Plane, inputs));
+        assert_eq!(plane(InputSet::default()), [0, 1, 2]);
+        let polar = InputSet {
+            polar: true,
+            bias: true,
+            ..Default::default()
         };
+        assert_eq!(plane(polar), [0, 1, 2, 3, 4]);
+        // The angle around the centre would tear at the seam of a cylinder
+        let cylinder = live_inputs(settings(StitchingType::Cylinder, polar));
+        assert_eq!(cylinder, [0, 1, 2, 4]);
+    }
+
+    #[test]
+    fn surface_inputs_meet_across_the_seam_without_mirroring() {
+        for stitching_type in [
+            StitchingType::Cylinder,
+            StitchingType::Sphere,
+            StitchingType::Torus,
+        ] {
+            let settings = settings(stitching_type, InputSet::default());
+            let position = |x| pixel_position(settings, x, 5, 32, 16);
+            let step = |a: [f32; 3], b: [f32; 3]| (0..3).map(|c| (a[c] - b[c]).abs()).sum::<f32>();
+            // The seam is no bigger a step than any other column
+            assert!(step(position(31), position(0)) <= step(position(0), position(1)) + 1e-5);
+            // Columns either side of the first are told apart
+            assert!(
+                step(position(1), position(31)) > 0.1,
+                "{:?}",
+                stitching_type
+            );
+        }
+    }
 
-        *pixel = egui::Color32::from_rgb(
-            (norm_r.clamp(0.0, 1.0) * 255.0) as u8,
-            (norm_g.clamp(0.0, 1.0) * 255.0) as u8,
-            (norm_b.clamp(0.0, 1.0) * 255.0) as u8,
+    #[test]
+    fn old_mappings_are_kept() {
+        let mut settings = settings(StitchingType::Cylinder, InputSet::default());
+        settings.input_mapping = InputMapping::Flat;
+        assert_eq!(
+            pixel_position(settings, 0, 0, 4, 3),
+            [-1.0, -1.0, 2f32.sqrt()]
+        );
+        settings.input_mapping = InputMapping::Mirrored;
+        // Mirrored about the first column
+        let (left, right) = (
+            pixel_position(settings, 1, 2, 4, 3),
+            pixel_position(settings, 3, 2, 4, 3),
         );
+        assert!((0..3).all(|c| (left[c] - right[c]).abs() < 1e-6));
     }
 
-    image
+    /// A single line of grey levels.
+    fn line(levels: &[f32]) -> Vec<[f32; 3]> {
+        levels.iter().map(|&level| [level; 3]).collect()
+    }
+
+    fn levels(values: &[[f32; 3]]) -> Vec<f32> {
+        values.iter().map(|value| value[0]).collect()
+    }
+
+    fn channel(values: &[[f32; 3]], c: usize) -> Vec<f32> {
+        values.iter().map(|value| value[c]).collect()
+    }
+
+    #[test]
+    fn per_channel_stretches_each_channel() {
+        let raw = [[-2.0, 0.0, 5.0], [0.0, 1.0, 5.0], [2.0, 4.0, 5.0]];
+        let values = normalize_outputs(&raw, Normalization::PerChannel);
+        assert_eq!(channel(&values, 0), [0.0, 0.5, 1.0]);
+        assert_eq!(channel(&values, 1), [0.0, 0.25, 1.0]);
+        // A flat channel sits in the middle
+        assert_eq!(channel(&values, 2), [0.5, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn uniform_keeps_proportions() {
+        let raw = [[-2.0, 0.0, 5.0], [0.0, 1.0, 5.0], [2.0, 2.0, 5.0]];
+        let values = normalize_outputs(&raw, Normalization::Uniform);
+        assert_eq!(channel(&values, 0), [0.0, 0.5, 1.0]);
+        // Half the range of x, centred
+        assert_eq!(channel(&values, 1), [0.25, 0.5, 0.75]);
+        assert_eq!(channel(&values, 2), [0.5, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn fixed_range_and_sigmoid_ignore_the_other_samples() {
+        let raw = [[-3.0, -1.0, 0.0], [0.5, 1.0, 9.0]];
+        let fixed = normalize_outputs(&raw, Normalization::FixedRange);
+        assert_eq!(fixed, [[0.0, 0.0, 0.5], [0.75, 1.0, 1.0]]);
+
+        let squashed = normalize_outputs(&raw, Normalization::Sigmoid);
+        assert_eq!(squashed[0][2], 0.5);
+        assert!(squashed.iter().flatten().all(|&v| (0.0..=1.0).contains(&v)));
+        assert!(squashed[0][0] < squashed[0][1] && squashed[1][0] < squashed[1][1]);
+    }
+
+    #[test]
+    fn percentile_clips_outliers() {
+        let mut raw: Vec<[f32; 3]> = (0..101).map(|i| [i as f32 / 100.0; 3]).collect();
+        raw[100] = [1000.0; 3];
+        let values = normalize_outputs(&raw, Normalization::Percentile);
+        // Two samples are clipped off each end
+        assert_eq!(values[0][0], 0.0);
+        assert_eq!(values[2][0], 0.0);
+        assert!((values[50][0] - 0.5).abs() < 0.01);
+        assert_eq!(values[98][0], 1.0);
+        assert_eq!(values[100][0], 1.0);
+
+        // Per channel, the outlier squeezes everything else to the bottom
+        let stretched = normalize_outputs(&raw, Normalization::PerChannel);
+        assert!(stretched[99][0] < 0.01);
+    }
+
+    #[test]
+    fn no_seam_treatment_leaves_values_alone() {
+        let mut values = line(&[0.0, 0.25, 0.5, 1.0]);
+        blend_seam(&mut values, SeamTreatment::None, 4, 1, 1, 4);
+        assert_eq!(levels(&values), [0.0, 0.25, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn edge_average_keeps_a_step_across_the_seam() {
+        let mut values = line(&[0.0, 0.25, 0.5, 0.625, 1.0]);
+        blend_seam(&mut values, SeamTreatment::EdgeAverage, 5, 1, 1, 5);
+        let blended = levels(&values);
+        // Evenly spaced from 0.625 over the seam to 0.25
+        assert!((blended[4] - 0.5).abs() < 1e-6);
+        assert!((blended[0] - 0.375).abs() < 1e-6);
+        assert_eq!(blended[1..4], [0.25, 0.5, 0.625]);
+        let seam_step = blended[0] - blended[4];
+        assert!(seam_step.abs() > 0.1);
+        assert!((seam_step - (blended[1] - blended[0])).abs() < 1e-6);
+    }
+
+    #[test]
+    fn crossfade_eases_over_a_band() {
+        let ramp: Vec<f32> = (0..16).map(|i| i as f32 / 15.0).collect();
+        let mut values = line(&ramp);
+        blend_seam(&mut values, SeamTreatment::Crossfade, 16, 1, 1, 16);
+
+        let blended = levels(&values);
+        // Two pixels each side, pulled three eighths and one eighth of the way
+        assert!((blended[0] - 0.375).abs() < 1e-6);
+        assert!((blended[15] - 0.625).abs() < 1e-6);
+        assert!((blended[1] - (ramp[1] + (ramp[14] - ramp[1]) * 0.125)).abs() < 1e-6);
+        assert_eq!(blended[2..14], ramp[2..14]);
+    }
+
+    #[test]
+    fn columns_blend_top_and_bottom_rows_only() {
+        // 2 wide, 4 tall, blended down every column
+        let mut values = line(&[1.0, 1.0, 0.0, 0.75, 0.75, 0.0, 1.0, 1.0]);
+        blend_seam(&mut values, SeamTreatment::EdgeAverage, 4, 2, 2, 1);
+        let expected = [0.25, 0.5, 0.0, 0.75, 0.75, 0.0, 0.5, 0.25];
+        for (blended, expected) in levels(&values).into_iter().zip(expected) {
+            assert!((blended - expected).abs() < 1e-6);
+        }
+    }
 }
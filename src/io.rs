@@ -1,18 +1,144 @@
 use bevy::prelude::*;
+use std::sync::{Arc, Mutex};
 
 // --- PUBLIC INTERFACE ---
 
+/// Describes a file type for the save/open dialogs and for browser downloads.
+#[derive(Clone, Copy, Debug)]
+pub struct FileFilter {
+    pub name: &'static str,
+    pub extensions: &'static [&'static str],
+    /// Only browser downloads need a MIME type, native dialogs go by extension.
+    #[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+    pub mime: &'static str,
+}
+
+pub const TGA_FILTER: FileFilter = FileFilter {
+    name: "TGA Image",
+    extensions: &["tga"],
+    mime: "image/tga",
+};
+
+pub const PNG_FILTER: FileFilter = FileFilter {
+    name: "PNG Image",
+    extensions: &["png"],
+    mime: "image/png",
+};
+
+pub const SCULPT_MAP_FILTER: FileFilter = FileFilter {
+    name: "Sculpt Map",
+    extensions: &["tga", "png"],
+    mime: "image/*",
+};
+
+pub const SESSION_FILTER: FileFilter = FileFilter {
+    name: "Evo-Sculptor Session",
+    extensions: &["evosession"],
+    mime: "application/json",
+};
+
+pub const GENOME_FILTER: FileFilter = FileFilter {
+    name: "Evo-Sculptor Genome",
+    extensions: &["genome"],
+    mime: "application/json",
+};
+
+pub const OBJ_FILTER: FileFilter = FileFilter {
+    name: "Wavefront OBJ",
+    extensions: &["obj"],
+    mime: "model/obj",
+};
+
+pub const GLB_FILTER: FileFilter = FileFilter {
+    name: "glTF Binary",
+    extensions: &["glb"],
+    mime: "model/gltf-binary",
+};
+
+pub const STL_FILTER: FileFilter = FileFilter {
+    name: "STL Model",
+    extensions: &["stl"],
+    mime: "model/stl",
+};
+
+pub const DAE_FILTER: FileFilter = FileFilter {
+    name: "COLLADA",
+    extensions: &["dae"],
+    mime: "model/vnd.collada+xml",
+};
+
+/// What a picked file should be used for once it has been read.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LoadPurpose {
+    Session,
+    /// Replace the genome in the given grid slot.
+    Genome {
+        slot: usize,
+    },
+    /// Show a sculpt map as a reference tile.
+    ReferenceMap,
+    /// Bake an OBJ mesh into a sculpt map.
+    BakeMesh,
+}
+
+/// A file picked by the user, waiting to be handled by the app.
+pub struct LoadedFile {
+    pub purpose: LoadPurpose,
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+/// Inbox for files read by the (asynchronous) open dialogs.
+/// Systems drain it every frame.
+#[derive(Resource, Default, Clone)]
+pub struct LoadedFiles(Arc<Mutex<Vec<LoadedFile>>>);
+
+impl LoadedFiles {
+    pub fn drain(&self) -> Vec<LoadedFile> {
+        self.0.lock().unwrap().drain(..).collect()
+    }
+
+    fn push(&self, file: LoadedFile) {
+        self.0.lock().unwrap().push(file);
+    }
+}
+
 /// Saves a byte array to a file.
 /// On Native: Opens a system "Save As" dialog.
 /// On Web: Triggers a browser download.
-pub fn save_sculpt_map(image_data: Vec<u8>, default_name: &str) {
-    save_file_impl(image_data, default_name);
+pub fn save_file(data: Vec<u8>, default_name: &str, filter: FileFilter) {
+    save_file_impl(data, default_name, filter, None);
+}
+
+/// Like `save_file`, also writing `sidecar` as a `.json` file with the same
+/// name next to the saved file (a second download on Web).
+pub fn save_file_with_sidecar(
+    data: Vec<u8>,
+    default_name: &str,
+    filter: FileFilter,
+    sidecar: Vec<u8>,
+) {
+    save_file_impl(data, default_name, filter, Some(sidecar));
+}
+
+/// Saves several files in one go.
+/// On Native: Asks for a directory and writes every file into it.
+/// On Web: Triggers a single zip download named `archive_name`.
+pub fn save_files(files: Vec<(String, Vec<u8>)>, archive_name: &str) {
+    save_files_impl(files, archive_name);
+}
+
+/// Lets the user pick a file and pushes its contents into `inbox`.
+/// On Native: Opens a system "Open" dialog.
+/// On Web: Opens the browser file picker.
+pub fn open_file(inbox: &LoadedFiles, purpose: LoadPurpose, filter: FileFilter) {
+    open_file_impl(inbox.clone(), purpose, filter);
 }
 
 // --- NATIVE IMPLEMENTATION ---
 
 #[cfg(not(target_arch = "wasm32"))]
-fn save_file_impl(data: Vec<u8>, default_name: &str) {
+fn save_file_impl(data: Vec<u8>, default_name: &str, filter: FileFilter, sidecar: Option<Vec<u8>>) {
     use rfd::FileDialog;
     use std::fs::write;
 
@@ -22,9 +148,14 @@ fn save_file_impl(data: Vec<u8>, default_name: &str) {
     std::thread::spawn(move || {
         if let Some(path) = FileDialog::new()
             .set_file_name(name) // 2. Use the owned String here
-            .add_filter("TGA Image", &["tga"])
+            .add_filter(filter.name, filter.extensions)
             .save_file()
         {
+            if let Some(sidecar) = sidecar
+                && let Err(e) = write(path.with_extension("json"), sidecar)
+            {
+                eprintln!("Failed to save sidecar file: {}", e);
+            }
             if let Err(e) = write(path, data) {
                 eprintln!("Failed to save file: {}", e);
             } else {
@@ -34,10 +165,52 @@ fn save_file_impl(data: Vec<u8>, default_name: &str) {
     });
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+fn save_files_impl(files: Vec<(String, Vec<u8>)>, _archive_name: &str) {
+    use rfd::FileDialog;
+    use std::fs::write;
+
+    std::thread::spawn(move || {
+        if let Some(directory) = FileDialog::new().pick_folder() {
+            for (name, data) in files {
+                if let Err(e) = write(directory.join(&name), data) {
+                    eprintln!("Failed to save {}: {}", name, e);
+                }
+            }
+            println!("Files saved to {}", directory.display());
+        }
+    });
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn open_file_impl(inbox: LoadedFiles, purpose: LoadPurpose, filter: FileFilter) {
+    use rfd::FileDialog;
+    use std::fs::read;
+
+    std::thread::spawn(move || {
+        if let Some(path) = FileDialog::new()
+            .add_filter(filter.name, filter.extensions)
+            .pick_file()
+        {
+            match read(&path) {
+                Ok(data) => inbox.push(LoadedFile {
+                    purpose,
+                    name: path
+                        .file_name()
+                        .map(|n| n.to_string_lossy().into_owned())
+                        .unwrap_or_default(),
+                    data,
+                }),
+                Err(e) => eprintln!("Failed to read file: {}", e),
+            }
+        }
+    });
+}
+
 // --- WASM IMPLEMENTATION ---
 
 #[cfg(target_arch = "wasm32")]
-fn save_file_impl(data: Vec<u8>, default_name: &str) {
+fn save_file_impl(data: Vec<u8>, default_name: &str, filter: FileFilter, sidecar: Option<Vec<u8>>) {
     use wasm_bindgen::JsCast;
     use web_sys::{Blob, BlobPropertyBag, HtmlAnchorElement, Url};
 
@@ -47,7 +220,7 @@ fn save_file_impl(data: Vec<u8>, default_name: &str) {
     parts.push(&uint8_array);
 
     let mut props = BlobPropertyBag::new();
-    props.type_("image/tga");
+    props.type_(filter.mime);
 
     let blob = Blob::new_with_u8_array_sequence_and_options(&parts, &props)
         .expect("Failed to create blob");
@@ -78,4 +251,63 @@ fn save_file_impl(data: Vec<u8>, default_name: &str) {
     Url::revoke_object_url(&url).unwrap();
 
     info!("Download triggered for {}", default_name);
+
+    if let Some(sidecar) = sidecar {
+        const JSON_FILTER: FileFilter = FileFilter {
+            name: "JSON",
+            extensions: &["json"],
+            mime: "application/json",
+        };
+        let stem = default_name
+            .rsplit_once('.')
+            .map_or(default_name, |(stem, _)| stem);
+        save_file_impl(sidecar, &format!("{}.json", stem), JSON_FILTER, None);
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn save_files_impl(files: Vec<(String, Vec<u8>)>, archive_name: &str) {
+    use std::io::{Cursor, Write};
+    use zip::write::SimpleFileOptions;
+
+    const ZIP_FILTER: FileFilter = FileFilter {
+        name: "Zip Archive",
+        extensions: &["zip"],
+        mime: "application/zip",
+    };
+
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    let mut archive = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, data) in files {
+        archive
+            .start_file(name, options)
+            .expect("Failed to start zip entry");
+        archive.write_all(&data).expect("Failed to write zip entry");
+    }
+    let data = archive
+        .finish()
+        .expect("Failed to finish zip archive")
+        .into_inner();
+
+    save_file_impl(data, archive_name, ZIP_FILTER, None);
+}
+
+#[cfg(target_arch = "wasm32")]
+fn open_file_impl(inbox: LoadedFiles, purpose: LoadPurpose, filter: FileFilter) {
+    use rfd::AsyncFileDialog;
+
+    wasm_bindgen_futures::spawn_local(async move {
+        if let Some(handle) = AsyncFileDialog::new()
+            .add_filter(filter.name, filter.extensions)
+            .pick_file()
+            .await
+        {
+            let data = handle.read().await;
+            inbox.push(LoadedFile {
+                purpose,
+                name: handle.file_name(),
+                data,
+            });
+        }
+    });
 }
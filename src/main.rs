@@ -4,10 +4,14 @@ use bevy_egui::{EguiPlugin, EguiPrimaryContextPass};
 use bevy_panorbit_camera::PanOrbitCameraPlugin;
 
 mod activations;
+mod bake;
 mod evolution;
+mod export;
+mod field;
 mod generator;
 mod io;
 mod sculpt;
+mod session;
 mod state;
 mod ui;
 
@@ -15,6 +19,14 @@ mod ui;
 pub struct Selectable {
     pub index: usize,
     pub is_selected: bool,
+    /// Preview resolution the tile's mesh was last built at.
+    pub resolution: usize,
+}
+
+/// Grid tile showing an imported sculpt map. It takes no part in evolution.
+#[derive(Component)]
+pub struct ReferenceTile {
+    pub index: usize,
 }
 
 fn main() {
@@ -35,8 +47,12 @@ fn main() {
             MeshPickingPlugin,
         ))
         .init_resource::<state::EvoState>()
+        .init_resource::<io::LoadedFiles>()
         .add_systems(Startup, ui::setup_camera_lights)
-        .add_systems(Update, ui::spawn_grid_system)
+        .add_systems(
+            Update,
+            (ui::handle_loaded_files, ui::spawn_grid_system).chain(),
+        )
         .add_systems(EguiPrimaryContextPass, ui::ui_system)
         .add_systems(
             Update,
@@ -45,6 +61,7 @@ fn main() {
                 evolution::log_activation_distribution,
                 evolution::evolve_system,
                 evolution::update_meshes_system,
+                export::gltf::export_glb_system,
             )
                 .chain(),
         )
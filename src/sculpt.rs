@@ -1,34 +1,44 @@
-use bevy_egui::egui;
+use bevy::asset::RenderAssetUsages;
+use bevy::prelude::*;
+use std::collections::HashMap;
 
-use crate::state::StitchingType;
+use crate::export::sculpt_map;
+use crate::field::SculptField;
+use crate::generator::{self, Genome};
+use crate::state::{EvoState, SculptFlags, StitchingType};
 
 pub struct SculptMeshData {
     pub vertices: Vec<[f32; 3]>,
     pub indices: bevy::mesh::Indices,
+    pub width: usize,
+    pub height: usize,
+    pub stitching_type: StitchingType,
 }
 
 pub fn create_sculpt_mesh(
-    image: &egui::ColorImage,
+    field: &SculptField,
     size: f32,
     stitching_type: StitchingType,
+    flags: SculptFlags,
 ) -> SculptMeshData {
-    let width = image.width();
-    let height = image.height();
+    let width = field.width();
+    let height = field.height();
 
-    let base_vertices: Vec<[f32; 3]> = image
-        .pixels
+    let mirror_x = if flags.mirror { -1.0 } else { 1.0 };
+    let mut base_vertices: Vec<[f32; 3]> = field
+        .samples()
         .iter()
-        .map(|pixel| {
-            let x = (pixel.r() as f32 / 255.0 - 0.5) * size;
-            let y = (pixel.g() as f32 / 255.0 - 0.5) * size;
-            let z = (pixel.b() as f32 / 255.0 - 0.5) * size;
+        .map(|&[r, g, b]| {
+            let x = (r - 0.5) * size * mirror_x;
+            let y = (g - 0.5) * size;
+            let z = (b - 0.5) * size;
             [x, y, z]
         })
         .collect();
 
     let mut indices: Vec<u32> = match stitching_type {
         StitchingType::Plane => Vec::with_capacity((width - 1) * (height - 1) * 6),
-        StitchingType::Sphere => Vec::with_capacity((width) * (height) * 6),
+        StitchingType::Sphere => Vec::with_capacity((width) * (height - 1) * 6),
         StitchingType::Cylinder => Vec::with_capacity((width) * (height - 1) * 6),
         StitchingType::Torus => Vec::with_capacity((width) * (height) * 6),
     };
@@ -49,32 +59,36 @@ pub fn create_sculpt_mesh(
             }
         }
         StitchingType::Sphere => {
-            // Cap Bottom
-            for x in 0..width {
-                insert_quad(&mut indices, 0, width + x, 0, width + ((x + 1) % width));
-            }
+            // Like the SL viewer, the first and last rows collapse into poles
+            // read from their middle pixel. Every quad touching a pole shares
+            // its single vertex, so the caps close without cracks.
+            let bottom_pole = width / 2;
+            let top_pole = (height - 1) * width + width / 2;
+            let (bottom, top) = (base_vertices[bottom_pole], base_vertices[top_pole]);
+            base_vertices[..width].fill(bottom);
+            base_vertices[(height - 1) * width..].fill(top);
+            let vertex = |x: usize, y: usize| {
+                if y == 0 {
+                    bottom_pole
+                } else if y == height - 1 {
+                    top_pole
+                } else {
+                    y * width + x
+                }
+            };
+
             // Stitching right to left
             for y in 0..(height - 1) {
                 for x in 0..width {
                     insert_quad(
                         &mut indices,
-                        y * width + x,
-                        y * width + x + width,
-                        y * width + ((x + 1) % width),
-                        y * width + width + ((x + 1) % width),
+                        vertex(x, y),
+                        vertex(x, y + 1),
+                        vertex((x + 1) % width, y),
+                        vertex((x + 1) % width, y + 1),
                     );
                 }
             }
-            // Cap Top
-            for x in 0..width {
-                insert_quad(
-                    &mut indices,
-                    (height - 2) * width + x,
-                    (height - 2) * width + width,
-                    (height - 2) * width + ((x + 1) % width),
-                    (height - 2) * width + width,
-                );
-            }
         }
         StitchingType::Cylinder => {
             // Stitching right to left
@@ -106,10 +120,334 @@ pub fn create_sculpt_mesh(
         }
     }
 
+    // Negating X turns the surface inside out too, so mirror undoes what invert does
+    if flags.mirror != flags.invert {
+        flip_winding(&mut indices);
+    }
+
     SculptMeshData {
         vertices: base_vertices,
         indices: bevy::mesh::Indices::U32(indices),
+        width,
+        height,
+        stitching_type,
+    }
+}
+
+/// Faces per side the Second Life viewer builds for a sculpt at each LOD, lowest first.
+pub const SL_LOD_SIDES: [usize; 4] = [4, 8, 16, 32];
+
+/// Meshes `field` the way the Second Life viewer does at `lod` (0 lowest, 3 highest).
+///
+/// The viewer does not mesh every pixel: it picks a vertex grid from the LOD
+/// and the map size, then samples the map at evenly spaced pixels, pinching
+/// the poles of spheres and wrapping seams by re-reading the first row or
+/// column. Mirror and invert are applied as the viewer does, by sampling the
+/// columns in reverse and negating X. The result is laid out as an open grid
+/// whose seams are duplicated vertices, so it is tagged as a Plane.
+pub fn create_sl_sculpt_mesh(
+    field: &SculptField,
+    size: f32,
+    stitching_type: StitchingType,
+    flags: SculptFlags,
+    lod: usize,
+) -> SculptMeshData {
+    let width = field.width();
+    let height = field.height();
+    let (sides_s, sides_t) = sl_mesh_resolution(width, height, lod);
+
+    // S runs down the map rows (the path), T across the columns (the profile)
+    let size_s = sides_s + 1;
+    let size_t = sides_t + 1;
+
+    let reverse_horizontal = flags.mirror != flags.invert;
+    let mirror_x = if flags.mirror { -1.0 } else { 1.0 };
+
+    let mut vertices = Vec::with_capacity(size_s * size_t);
+    for s in 0..size_s {
+        for t in 0..size_t {
+            let t = if reverse_horizontal {
+                size_t - 1 - t
+            } else {
+                t
+            };
+            let mut x = (t as f32 / (size_t - 1) as f32 * width as f32) as usize;
+            let mut y = (s as f32 / (size_s - 1) as f32 * height as f32) as usize;
+
+            if y == 0 && stitching_type == StitchingType::Sphere {
+                x = width / 2;
+            }
+            if y == height {
+                y = if stitching_type == StitchingType::Torus {
+                    0
+                } else {
+                    height - 1
+                };
+                if stitching_type == StitchingType::Sphere {
+                    x = width / 2;
+                }
+            }
+            if x == width {
+                x = if stitching_type == StitchingType::Plane {
+                    width - 1
+                } else {
+                    0
+                };
+            }
+
+            let [r, g, b] = field.sample(x, y);
+            vertices.push([
+                (r - 0.5) * size * mirror_x,
+                (g - 0.5) * size,
+                (b - 0.5) * size,
+            ]);
+        }
+    }
+
+    let mut indices = Vec::with_capacity(sides_s * sides_t * 6);
+    for y in 0..sides_s {
+        for x in 0..sides_t {
+            insert_quad(
+                &mut indices,
+                y * size_t + x,
+                y * size_t + x + size_t,
+                y * size_t + x + 1,
+                y * size_t + x + size_t + 1,
+            );
+        }
+    }
+
+    SculptMeshData {
+        vertices,
+        indices: bevy::mesh::Indices::U32(indices),
+        width: size_t,
+        height: size_s,
+        stitching_type: StitchingType::Plane,
+    }
+}
+
+/// Faces along S and T for a sculpt map, following the viewer's
+/// `sculpt_calc_mesh_resolution`: as many vertices as the LOD allows, no more
+/// than a quarter of the map's pixels, in the map's aspect ratio. Maps too
+/// small for that still get at least one face along S.
+fn sl_mesh_resolution(width: usize, height: usize, lod: usize) -> (usize, usize) {
+    let max_vertices_lod = SL_LOD_SIDES[lod.min(SL_LOD_SIDES.len() - 1)].pow(2);
+    let max_vertices_map = width * height / 4;
+    let vertices = if max_vertices_map > 0 {
+        max_vertices_lod.min(max_vertices_map)
+    } else {
+        max_vertices_lod
+    };
+
+    let ratio = width as f32 / height as f32;
+    let s = ((vertices as f32 / ratio).sqrt() as usize).max(4);
+    let t = (vertices / s).max(4);
+    let s = (vertices / t).max(1);
+
+    (s, t)
+}
+
+/// Meshes a sculpt map for the grid, in full or as the SL viewer would at the previewed LOD.
+pub fn create_preview_mesh(field: &SculptField, evo_state: &EvoState) -> SculptMeshData {
+    match evo_state.sl_lod_preview {
+        Some(lod) => create_sl_sculpt_mesh(
+            field,
+            5.0,
+            evo_state.stitching_type,
+            evo_state.sculpt_flags,
+            lod,
+        ),
+        None => create_sculpt_mesh(field, 5.0, evo_state.stitching_type, evo_state.sculpt_flags),
+    }
+}
+
+/// Meshes the sculpt map of a genome for the grid at `resolution`. The SL LOD
+/// preview samples the map as it will be exported, since that is what the
+/// viewer receives. Otherwise the field is rounded to its 8-bit levels, unless
+/// float geometry asks for the network output itself.
+pub fn create_genome_preview_mesh(
+    topology: &Genome,
+    evo_state: &EvoState,
+    resolution: usize,
+) -> SculptMeshData {
+    let field = if evo_state.sl_lod_preview.is_some() {
+        let sculpt_map = sculpt_map::render_sculpt_map(
+            topology,
+            &evo_state.sculpt_map_settings,
+            evo_state.image_settings(),
+        );
+        sculpt_map::dynamic_to_field(&sculpt_map)
+    } else {
+        let settings = evo_state.image_settings();
+        let [width, height] = settings.aspect.dimensions(resolution);
+        let field = generator::generate_field(topology, width, height, settings);
+        if evo_state.float_geometry {
+            field
+        } else {
+            field.quantized()
+        }
+    };
+    create_preview_mesh(&field, evo_state)
+}
+
+/// Samples a smaller field out of `field`. Axes that are not wrapped by
+/// `stitching_type` keep their first and last sample so the border stays put.
+pub fn downsample_field(
+    field: &SculptField,
+    width: usize,
+    height: usize,
+    stitching_type: StitchingType,
+) -> SculptField {
+    let (wrap_x, wrap_y) = stitching_type.wrapped_axes();
+    let sample = |i: usize, target: usize, source: usize, wrap: bool| {
+        if wrap || target < 2 {
+            i * source / target
+        } else {
+            (i * (source - 1) + (target - 1) / 2) / (target - 1)
+        }
+    };
+
+    let mut samples = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let source_y = sample(y, height, field.height(), wrap_y);
+        for x in 0..width {
+            let source_x = sample(x, width, field.width(), wrap_x);
+            samples.push(field.sample(source_x, source_y));
+        }
+    }
+
+    SculptField::new(width, height, samples)
+}
+
+/// True for triangles that reuse a vertex, such as those of the sphere caps.
+pub fn is_collapsed_triangle(triangle: &[usize]) -> bool {
+    triangle[0] == triangle[1] || triangle[1] == triangle[2] || triangle[0] == triangle[2]
+}
+
+/// Computes the same smooth normals the preview uses, one per vertex.
+pub fn compute_smooth_normals(data: &SculptMeshData) -> Vec<[f32; 3]> {
+    let mut mesh = Mesh::new(
+        bevy::mesh::PrimitiveTopology::TriangleList,
+        RenderAssetUsages::MAIN_WORLD,
+    );
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, data.vertices.clone());
+    mesh.insert_indices(data.indices.clone());
+    mesh.compute_smooth_normals();
+
+    mesh.attribute(Mesh::ATTRIBUTE_NORMAL)
+        .and_then(|values| values.as_float3())
+        .map(|normals| normals.to_vec())
+        .unwrap_or_default()
+}
+
+/// Lays texture coordinates over the sculpt grid for its stitching type.
+///
+/// Returns the UV grid and, for every entry of `data.indices`, the index of
+/// the UV to use for that triangle corner. Wrapped axes get an extra column
+/// (or row) so that triangles crossing a seam run up to 1.0 instead of
+/// jumping back to 0.0.
+pub fn compute_sculpt_uvs(data: &SculptMeshData) -> (Vec<[f32; 2]>, Vec<u32>) {
+    let width = data.width;
+    let height = data.height;
+    let (wrap_x, wrap_y) = data.stitching_type.wrapped_axes();
+
+    let uv_width = if wrap_x { width + 1 } else { width };
+    let uv_height = if wrap_y { height + 1 } else { height };
+
+    let mut uvs = Vec::with_capacity(uv_width * uv_height);
+    for y in 0..uv_height {
+        for x in 0..uv_width {
+            let u = x as f32 / (uv_width - 1) as f32;
+            let v = y as f32 / (uv_height - 1) as f32;
+            // Image row 0 is the top of the sculpt map, UV origin is bottom left
+            uvs.push([u, 1.0 - v]);
+        }
     }
+
+    let corners: Vec<usize> = data.indices.iter().collect();
+    let mut uv_indices = Vec::with_capacity(corners.len());
+    for triangle in corners.chunks_exact(3) {
+        let xs = [
+            triangle[0] % width,
+            triangle[1] % width,
+            triangle[2] % width,
+        ];
+        let ys = [
+            triangle[0] / width,
+            triangle[1] / width,
+            triangle[2] / width,
+        ];
+        let crosses_x = wrap_x && xs.contains(&0) && xs.contains(&(width - 1));
+        let crosses_y = wrap_y && ys.contains(&0) && ys.contains(&(height - 1));
+
+        for (&x, &y) in xs.iter().zip(ys.iter()) {
+            let x = if crosses_x && x == 0 { width } else { x };
+            let y = if crosses_y && y == 0 { height } else { y };
+            uv_indices.push((y * uv_width + x) as u32);
+        }
+    }
+
+    (uvs, uv_indices)
+}
+
+/// Vertex streams of a sculpt mesh with one index per vertex, as GPUs and glTF expect.
+pub struct UnweldedMesh {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub uvs: Vec<[f32; 2]>,
+    pub indices: Vec<u32>,
+}
+
+/// Vertices on a UV seam are duplicated, one copy per UV they are used with.
+/// Normals are computed before the split so the seam stays smooth.
+pub fn unweld_uv_seams(data: &SculptMeshData) -> UnweldedMesh {
+    let normals = compute_smooth_normals(data);
+    let (uv_grid, uv_indices) = compute_sculpt_uvs(data);
+    let corners: Vec<usize> = data.indices.iter().collect();
+
+    let mut remap: HashMap<(usize, u32), u32> = HashMap::new();
+    let mut positions = Vec::with_capacity(data.vertices.len());
+    let mut out_normals = Vec::with_capacity(data.vertices.len());
+    let mut uvs = Vec::with_capacity(data.vertices.len());
+    let mut indices = Vec::with_capacity(corners.len());
+
+    for (triangle, uv_triangle) in corners.chunks_exact(3).zip(uv_indices.chunks_exact(3)) {
+        if is_collapsed_triangle(triangle) {
+            continue;
+        }
+        for (&vertex, &uv) in triangle.iter().zip(uv_triangle) {
+            let index = *remap.entry((vertex, uv)).or_insert_with(|| {
+                positions.push(data.vertices[vertex]);
+                out_normals.push(normals[vertex]);
+                // Bevy and glTF put the UV origin at the top left
+                let [u, v] = uv_grid[uv as usize];
+                uvs.push([u, 1.0 - v]);
+                (positions.len() - 1) as u32
+            });
+            indices.push(index);
+        }
+    }
+
+    UnweldedMesh {
+        positions,
+        normals: out_normals,
+        uvs,
+        indices,
+    }
+}
+
+/// Replaces the geometry of a grid tile's mesh with `data`, textured over the sculpt grid.
+pub fn insert_preview_attributes(mesh: &mut Mesh, data: &SculptMeshData) {
+    let UnweldedMesh {
+        positions,
+        normals,
+        uvs,
+        indices,
+    } = unweld_uv_seams(data);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.insert_indices(bevy::mesh::Indices::U32(indices));
 }
 
 fn insert_quad(indices: &mut Vec<u32>, a: usize, b: usize, c: usize, d: usize) {
@@ -121,3 +459,155 @@ fn insert_quad(indices: &mut Vec<u32>, a: usize, b: usize, c: usize, d: usize) {
     indices.push(b as u32);
     indices.push(d as u32);
 }
+
+fn flip_winding(indices: &mut [u32]) {
+    for triangle in indices.chunks_exact_mut(3) {
+        triangle.swap(1, 2);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `width` x `height` map of a flat sheet in the XY plane.
+    fn sheet(width: usize, height: usize) -> SculptField {
+        let samples = (0..width * height)
+            .map(|i| {
+                let x = (i % width) as f32 / (width - 1) as f32;
+                let y = (i / width) as f32 / (height - 1) as f32;
+                [x, y, 0.5]
+            })
+            .collect();
+        SculptField::new(width, height, samples)
+    }
+
+    fn flags(mirror: bool, invert: bool) -> SculptFlags {
+        SculptFlags { mirror, invert }
+    }
+
+    /// Z of the facet normal of every triangle, which way the sheet faces.
+    fn facing(data: &SculptMeshData) -> Vec<f32> {
+        let corners: Vec<usize> = data.indices.iter().collect();
+        corners
+            .chunks_exact(3)
+            .map(|triangle| {
+                let [a, b, c] = [0, 1, 2].map(|k| Vec3::from(data.vertices[triangle[k]]));
+                (b - a).cross(c - a).z.signum()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn invert_flips_winding_and_mirror_keeps_the_facing() {
+        let field = sheet(4, 3);
+        let mesh = |mirror, invert| {
+            create_sculpt_mesh(&field, 1.0, StitchingType::Plane, flags(mirror, invert))
+        };
+        let plain = mesh(false, false);
+        let front = facing(&plain);
+        assert!(front.iter().all(|&z| z == front[0]));
+        let back: Vec<f32> = front.iter().map(|z| -z).collect();
+
+        assert_eq!(facing(&mesh(false, true)), back);
+        assert_eq!(facing(&mesh(true, false)), front);
+        assert_eq!(facing(&mesh(true, true)), back);
+
+        // Mirror alone reverses the corners, mirror and invert together keep them
+        let mut flipped: Vec<u32> = plain.indices.iter().map(|i| i as u32).collect();
+        flip_winding(&mut flipped);
+        let corners =
+            |data: SculptMeshData| -> Vec<u32> { data.indices.iter().map(|i| i as u32).collect() };
+        assert_eq!(corners(mesh(true, false)), flipped);
+        assert_eq!(corners(mesh(false, true)), flipped);
+        assert_eq!(corners(mesh(true, true)), corners(plain));
+    }
+
+    #[test]
+    fn sl_lods_follow_the_viewer_resolution() {
+        let field = sheet(64, 64);
+        for (lod, sides) in SL_LOD_SIDES.into_iter().enumerate() {
+            let data =
+                create_sl_sculpt_mesh(&field, 1.0, StitchingType::Plane, flags(false, false), lod);
+            assert_eq!((data.width, data.height), (sides + 1, sides + 1));
+            assert_eq!(data.indices.len(), sides * sides * 6);
+        }
+        // No more vertices than a quarter of the pixels
+        assert_eq!(sl_mesh_resolution(16, 16, 3), (8, 8));
+        assert_eq!(sl_mesh_resolution(128, 8, 3), (4, 64));
+    }
+
+    #[test]
+    fn sl_meshes_sample_seams_and_poles_like_the_viewer() {
+        let field = sheet(16, 16);
+        let vertex_row = |data: &SculptMeshData, row: usize| {
+            data.vertices[row * data.width..(row + 1) * data.width].to_vec()
+        };
+        let mesh = |stitching_type, lod| {
+            create_sl_sculpt_mesh(&field, 1.0, stitching_type, flags(false, false), lod)
+        };
+
+        // Wrapped columns re-read the first one, a plane reads the last
+        let cylinder = mesh(StitchingType::Cylinder, 1);
+        for row in cylinder.vertices.chunks_exact(cylinder.width) {
+            assert_eq!(row[0], row[cylinder.width - 1]);
+        }
+        let plane = mesh(StitchingType::Plane, 1);
+        assert_eq!(plane.vertices[plane.width - 1][0], 0.5);
+
+        // Sphere poles read the middle pixel of the first and last rows
+        let sphere = mesh(StitchingType::Sphere, 1);
+        let pole = field.sample(8, 0);
+        assert!(
+            vertex_row(&sphere, 0)
+                .iter()
+                .all(|&v| v[0] == pole[0] - 0.5)
+        );
+        let last = sphere.height - 1;
+        assert!(vertex_row(&sphere, last).iter().all(|&v| v[1] == 0.5));
+
+        // A torus wraps its last row back to the first
+        let torus = mesh(StitchingType::Torus, 1);
+        assert_eq!(vertex_row(&torus, 0), vertex_row(&torus, torus.height - 1));
+    }
+
+    #[test]
+    fn sl_meshes_mirror_by_reversing_columns() {
+        let field = sheet(16, 16);
+        let mesh = |mirror, invert| {
+            create_sl_sculpt_mesh(&field, 1.0, StitchingType::Plane, flags(mirror, invert), 1)
+        };
+        let plain = mesh(false, false);
+        let inverted = mesh(false, true);
+        for (row, inverted_row) in plain
+            .vertices
+            .chunks_exact(plain.width)
+            .zip(inverted.vertices.chunks_exact(inverted.width))
+        {
+            let reversed: Vec<_> = row.iter().rev().copied().collect();
+            assert_eq!(inverted_row, reversed);
+        }
+        // Mirror and invert together sample in order, with X negated
+        let both = mesh(true, true);
+        for (vertex, mirrored) in plain.vertices.iter().zip(&both.vertices) {
+            assert_eq!([-vertex[0], vertex[1], vertex[2]], *mirrored);
+        }
+    }
+
+    #[test]
+    fn tiny_maps_still_get_an_sl_mesh() {
+        for (width, height) in [(2, 2), (4, 2), (2, 8)] {
+            let (s, t) = sl_mesh_resolution(width, height, 0);
+            assert!(s >= 1 && t >= 1);
+            let data = create_sl_sculpt_mesh(
+                &sheet(width, height),
+                1.0,
+                StitchingType::Plane,
+                flags(false, false),
+                0,
+            );
+            assert!(!data.indices.is_empty());
+            assert!(data.vertices.iter().flatten().all(|v| v.is_finite()));
+        }
+    }
+}
@@ -0,0 +1,395 @@
+use neat::activation::{ActivationFn, ActivationScope, linear_activation};
+use neat::rand::thread_rng;
+use neat::{NNTSerde, NeuralNetworkTopology, NeuronTopology, activation_fn};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
+
+use crate::generator::{self, CPPN_INPUTS, Genome};
+use crate::state::{
+    DEFAULT_PRIM_SCALE, EvoState, GenomeSettings, InputMapping, InputSet, MAX_GRID_SIZE,
+    MAX_PRIM_SCALE, MIN_PRIM_SCALE, Normalization, SculptAspect, SculptFlags, SeamTreatment,
+    StitchingType,
+};
+
+/// Bumped whenever the layout of `SessionFile` changes incompatibly.
+/// Version 1 files are still read, see `StoredGenome`. Files before version
+/// 3 keep the input mapping they were evolved with, see `StoredSeamTreatment`.
+pub const SESSION_VERSION: u32 = 3;
+
+/// Bumped whenever the layout of `GenomeFile` changes incompatibly.
+/// Version 1 files are still read, see `StoredGenome`. Files before version
+/// 3 hold no settings and were evolved on flat map coordinates.
+pub const GENOME_VERSION: u32 = 3;
+
+/// A genome as stored on disk. Version 1 files hold networks with only the
+/// x, y and distance inputs, which are widened on load.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum StoredGenome {
+    Current(Box<NNTSerde<CPPN_INPUTS, 3>>),
+    Legacy(Box<NNTSerde<3, 3>>),
+}
+
+impl From<&Genome> for StoredGenome {
+    fn from(genome: &Genome) -> Self {
+        StoredGenome::Current(Box::new(NNTSerde::from(genome)))
+    }
+}
+
+impl From<StoredGenome> for Genome {
+    fn from(stored: StoredGenome) -> Self {
+        match stored {
+            StoredGenome::Current(serde) => NeuralNetworkTopology::from(*serde),
+            StoredGenome::Legacy(legacy) => widen_genome(NeuralNetworkTopology::from(*legacy)),
+        }
+    }
+}
+
+/// Gives a network with only the x, y and distance inputs room for the rest.
+/// The new inputs are plain linear ones, and nothing is connected to them yet.
+fn widen_genome(legacy: NeuralNetworkTopology<3, 3>) -> Genome {
+    let input_layer = std::array::from_fn(|i| match legacy.input_layer.get(i) {
+        Some(neuron) => neuron.clone(),
+        None => Arc::new(RwLock::new(NeuronTopology {
+            inputs: Vec::new(),
+            bias: 0.0,
+            activation: activation_fn!(linear_activation),
+        })),
+    });
+
+    NeuralNetworkTopology {
+        input_layer,
+        hidden_layers: legacy.hidden_layers,
+        output_layer: legacy.output_layer,
+        mutation_rate: legacy.mutation_rate,
+        mutation_passes: legacy.mutation_passes,
+    }
+}
+
+/// On-disk representation of a whole breeding session.
+#[derive(Serialize, Deserialize)]
+struct SessionFile {
+    version: u32,
+    generation: u64,
+    grid_size: usize,
+    stitching_type: StitchingType,
+    #[serde(default)]
+    sculpt_flags: SculptFlags,
+    #[serde(default)]
+    sculpt_aspect: SculptAspect,
+    #[serde(default)]
+    seam_treatment: StoredSeamTreatment,
+    #[serde(default)]
+    input_set: InputSet,
+    /// Missing before version 3, which derives it from the seam treatment.
+    #[serde(default)]
+    input_mapping: InputMapping,
+    #[serde(default)]
+    normalization: Normalization,
+    #[serde(default = "default_prim_scale")]
+    prim_scale: f32,
+    fitness: Vec<f32>,
+    genomes: Vec<StoredGenome>,
+    /// Companion texture CPPNs, missing from sessions saved before textures.
+    #[serde(default)]
+    textures: Vec<StoredGenome>,
+    #[serde(default)]
+    texture_enabled: bool,
+}
+
+fn default_prim_scale() -> f32 {
+    DEFAULT_PRIM_SCALE
+}
+
+/// A seam treatment as stored on disk. Before version 3 the cosine inputs
+/// of `InputMapping::Mirrored` were picked as the "Periodic" seam treatment.
+#[derive(Serialize, Deserialize, Default)]
+enum StoredSeamTreatment {
+    #[default]
+    None,
+    Crossfade,
+    EdgeAverage,
+    Periodic,
+}
+
+impl From<SeamTreatment> for StoredSeamTreatment {
+    fn from(treatment: SeamTreatment) -> Self {
+        match treatment {
+            SeamTreatment::None => StoredSeamTreatment::None,
+            SeamTreatment::Crossfade => StoredSeamTreatment::Crossfade,
+            SeamTreatment::EdgeAverage => StoredSeamTreatment::EdgeAverage,
+        }
+    }
+}
+
+impl From<StoredSeamTreatment> for SeamTreatment {
+    fn from(stored: StoredSeamTreatment) -> Self {
+        match stored {
+            StoredSeamTreatment::None | StoredSeamTreatment::Periodic => SeamTreatment::None,
+            StoredSeamTreatment::Crossfade => SeamTreatment::Crossfade,
+            StoredSeamTreatment::EdgeAverage => SeamTreatment::EdgeAverage,
+        }
+    }
+}
+
+/// On-disk representation of a single shared genome.
+#[derive(Serialize, Deserialize)]
+struct GenomeFile {
+    version: u32,
+    genome: StoredGenome,
+    #[serde(default)]
+    texture: Option<StoredGenome>,
+    /// Missing before version 3.
+    #[serde(default)]
+    settings: Option<GenomeSettings>,
+}
+
+/// Serializes the population, fitness marks and grid settings of `evo_state`.
+pub fn session_to_bytes(evo_state: &EvoState) -> Vec<u8> {
+    let session = SessionFile {
+        version: SESSION_VERSION,
+        generation: evo_state.generation,
+        grid_size: evo_state.grid_size,
+        stitching_type: evo_state.stitching_type,
+        sculpt_flags: evo_state.sculpt_flags,
+        sculpt_aspect: evo_state.sculpt_aspect,
+        seam_treatment: StoredSeamTreatment::from(evo_state.seam_treatment),
+        input_set: evo_state.input_set,
+        input_mapping: evo_state.input_mapping,
+        normalization: evo_state.normalization,
+        prim_scale: evo_state.prim_scale,
+        fitness: evo_state.fitness.clone(),
+        genomes: evo_state.genomes.iter().map(StoredGenome::from).collect(),
+        textures: evo_state.textures.iter().map(StoredGenome::from).collect(),
+        texture_enabled: evo_state.texture_enabled,
+    };
+
+    serde_json::to_vec_pretty(&session).expect("Failed to serialize session")
+}
+
+/// Replaces the session held in `evo_state` with the one stored in `data`.
+/// On success the grid is respawned to match the loaded population.
+pub fn load_session(evo_state: &mut EvoState, data: &[u8]) -> Result<(), String> {
+    let session: SessionFile =
+        serde_json::from_slice(data).map_err(|e| format!("Invalid session file: {}", e))?;
+
+    if !(1..=SESSION_VERSION).contains(&session.version) {
+        return Err(format!(
+            "Unsupported session version {} (expected up to {})",
+            session.version, SESSION_VERSION
+        ));
+    }
+
+    let population = session
+        .grid_size
+        .checked_mul(session.grid_size)
+        .filter(|_| (1..=MAX_GRID_SIZE).contains(&session.grid_size))
+        .ok_or_else(|| {
+            format!(
+                "Unsupported grid size {} (expected up to {})",
+                session.grid_size, MAX_GRID_SIZE
+            )
+        })?;
+    if session.genomes.len() != population || session.fitness.len() != population {
+        return Err(format!(
+            "Session holds {} genomes and {} fitness marks for a {}x{} grid",
+            session.genomes.len(),
+            session.fitness.len(),
+            session.grid_size,
+            session.grid_size
+        ));
+    }
+
+    evo_state.genomes = session.genomes.into_iter().map(Genome::from).collect();
+    evo_state.texture_enabled = session.texture_enabled;
+    evo_state.fitness = session.fitness;
+    evo_state.generation = session.generation;
+    evo_state.grid_size = session.grid_size;
+    evo_state.stitching_type = session.stitching_type;
+    evo_state.sculpt_flags = session.sculpt_flags;
+    evo_state.sculpt_aspect = session.sculpt_aspect;
+    // Older sessions were evolved on flat map coordinates
+    evo_state.input_mapping = match session.seam_treatment {
+        _ if session.version >= 3 => session.input_mapping,
+        StoredSeamTreatment::Periodic => InputMapping::Mirrored,
+        _ => InputMapping::Flat,
+    };
+    evo_state.seam_treatment = SeamTreatment::from(session.seam_treatment);
+    evo_state.input_set = session.input_set;
+    evo_state.normalization = session.normalization;
+    evo_state.prim_scale = session.prim_scale.clamp(MIN_PRIM_SCALE, MAX_PRIM_SCALE);
+    // Older sessions get fresh textures, fed like the rest of the session
+    let mut rng = thread_rng();
+    evo_state.textures = if session.textures.len() == population {
+        session.textures.into_iter().map(Genome::from).collect()
+    } else {
+        let live = generator::live_inputs(evo_state.image_settings());
+        (0..population)
+            .map(|_| EvoState::random_genome(&live, &mut rng))
+            .collect()
+    };
+    evo_state.import_slot = evo_state.import_slot.min(population - 1);
+    evo_state.imported_settings = None;
+    evo_state.clear_map_reports();
+    evo_state.grid_spawn_requested = true;
+
+    Ok(())
+}
+
+/// Serializes the genome of tile `index`, with its companion texture and the
+/// settings it is bred with, so it can be shared and re-bred later.
+pub fn genome_to_bytes(evo_state: &EvoState, index: usize) -> Vec<u8> {
+    let file = GenomeFile {
+        version: GENOME_VERSION,
+        genome: StoredGenome::from(&evo_state.genomes[index]),
+        texture: Some(StoredGenome::from(&evo_state.textures[index])),
+        settings: Some(evo_state.genome_settings()),
+    };
+
+    serde_json::to_vec_pretty(&file).expect("Failed to serialize genome")
+}
+
+/// Reads a genome written by `genome_to_bytes`, its texture if the file has
+/// one, and the settings it was evolved with.
+pub fn genome_from_bytes(data: &[u8]) -> Result<(Genome, Option<Genome>, GenomeSettings), String> {
+    let file: GenomeFile =
+        serde_json::from_slice(data).map_err(|e| format!("Invalid genome file: {}", e))?;
+
+    if !(1..=GENOME_VERSION).contains(&file.version) {
+        return Err(format!(
+            "Unsupported genome version {} (expected up to {})",
+            file.version, GENOME_VERSION
+        ));
+    }
+
+    // Older genomes were evolved on flat map coordinates with the default inputs
+    let settings = file.settings.unwrap_or(GenomeSettings {
+        input_mapping: InputMapping::Flat,
+        ..Default::default()
+    });
+
+    Ok((
+        Genome::from(file.genome),
+        file.texture.map(Genome::from),
+        settings,
+    ))
+}
+
+/// Stable 64-bit FNV-1a hash of the serialized genome, the same across runs and machines.
+pub fn genome_hash(genome: &Genome) -> u64 {
+    let bytes = serde_json::to_vec(&NNTSerde::from(genome)).expect("Failed to serialize genome");
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A saved session, rewritten as an older version would have stored it.
+    fn old_session(version: u32, seam_treatment: &str) -> Vec<u8> {
+        crate::activations::register_custom_activations();
+        let mut session: serde_json::Value =
+            serde_json::from_slice(&session_to_bytes(&EvoState::default())).unwrap();
+        session["version"] = version.into();
+        session["seam_treatment"] = seam_treatment.into();
+        session.as_object_mut().unwrap().remove("input_mapping");
+        serde_json::to_vec(&session).unwrap()
+    }
+
+    #[test]
+    fn sessions_round_trip() {
+        crate::activations::register_custom_activations();
+        let mut saved = EvoState {
+            generation: 12,
+            texture_enabled: true,
+            stitching_type: StitchingType::Torus,
+            ..Default::default()
+        };
+        saved.resize_grid(3);
+        saved.fitness[4] = 1.0;
+        let bytes = session_to_bytes(&saved);
+
+        let mut loaded = EvoState::default();
+        load_session(&mut loaded, &bytes).unwrap();
+        assert_eq!(loaded.grid_size, 3);
+        assert_eq!(loaded.generation, 12);
+        assert_eq!(loaded.fitness, saved.fitness);
+        assert!(loaded.texture_enabled);
+        assert_eq!(loaded.stitching_type, StitchingType::Torus);
+        assert!(loaded.grid_spawn_requested);
+        let hashes = |genomes: &[Genome]| genomes.iter().map(genome_hash).collect::<Vec<_>>();
+        assert_eq!(hashes(&loaded.genomes), hashes(&saved.genomes));
+        assert_eq!(hashes(&loaded.textures), hashes(&saved.textures));
+    }
+
+    #[test]
+    fn oversized_grids_are_rejected() {
+        crate::activations::register_custom_activations();
+        let mut session: serde_json::Value =
+            serde_json::from_slice(&session_to_bytes(&EvoState::default())).unwrap();
+        for grid_size in [0, MAX_GRID_SIZE + 1, usize::MAX] {
+            session["grid_size"] = grid_size.into();
+            let mut evo_state = EvoState::default();
+            let error = load_session(&mut evo_state, &serde_json::to_vec(&session).unwrap());
+            assert!(error.unwrap_err().contains("Unsupported grid size"));
+            assert_eq!(evo_state.grid_size, 4);
+        }
+    }
+
+    #[test]
+    fn sessions_keep_their_input_mapping() {
+        crate::activations::register_custom_activations();
+        let mut evo_state = EvoState {
+            input_mapping: InputMapping::Mirrored,
+            ..Default::default()
+        };
+        let bytes = session_to_bytes(&evo_state);
+        evo_state.input_mapping = InputMapping::Surface;
+        load_session(&mut evo_state, &bytes).unwrap();
+        assert_eq!(evo_state.input_mapping, InputMapping::Mirrored);
+    }
+
+    #[test]
+    fn older_sessions_load_with_flat_coordinates() {
+        let mut evo_state = EvoState::default();
+        load_session(&mut evo_state, &old_session(2, "Crossfade")).unwrap();
+        assert_eq!(evo_state.input_mapping, InputMapping::Flat);
+        assert_eq!(evo_state.seam_treatment, SeamTreatment::Crossfade);
+
+        load_session(&mut evo_state, &old_session(2, "Periodic")).unwrap();
+        assert_eq!(evo_state.input_mapping, InputMapping::Mirrored);
+        assert_eq!(evo_state.seam_treatment, SeamTreatment::None);
+    }
+
+    #[test]
+    fn genomes_carry_their_settings() {
+        crate::activations::register_custom_activations();
+        let evo_state = EvoState {
+            input_mapping: InputMapping::Mirrored,
+            normalization: Normalization::Sigmoid,
+            ..Default::default()
+        };
+        let bytes = genome_to_bytes(&evo_state, 1);
+        let (genome, texture, settings) = genome_from_bytes(&bytes).unwrap();
+        assert_eq!(settings, evo_state.genome_settings());
+        assert_eq!(genome_hash(&genome), genome_hash(&evo_state.genomes[1]));
+        assert_eq!(
+            genome_hash(&texture.unwrap()),
+            genome_hash(&evo_state.textures[1])
+        );
+    }
+
+    #[test]
+    fn older_genomes_load_with_flat_coordinates() {
+        crate::activations::register_custom_activations();
+        let mut file: serde_json::Value =
+            serde_json::from_slice(&genome_to_bytes(&EvoState::default(), 0)).unwrap();
+        file["version"] = 2.into();
+        file.as_object_mut().unwrap().remove("settings");
+        let (_, _, settings) = genome_from_bytes(&serde_json::to_vec(&file).unwrap()).unwrap();
+        assert_eq!(settings.input_mapping, InputMapping::Flat);
+        assert_eq!(settings.input_set, InputSet::default());
+    }
+}
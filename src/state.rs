@@ -1,11 +1,19 @@
 use crate::activations::*;
+use crate::export::land_impact::SizeReport;
+use crate::export::quantisation::QuantisationReport;
+use crate::export::sculpt_map::SculptMapSettings;
+use crate::export::stl::MeshReport;
+use crate::export::validate::MapReport;
+use crate::field::SculptField;
+use crate::generator::{self, Genome, ImageSettings};
 use bevy::prelude::*;
 use neat::activation::{ActivationScope, linear_activation, relu, sigmoid};
-use neat::rand::{Rng, thread_rng};
-use neat::{ActivationFn, NeuralNetworkTopology, activation_fn};
+use neat::rand::{Rng, seq::SliceRandom, thread_rng};
+use neat::{ActivationFn, NeuralNetworkTopology, NeuronLocation, activation_fn};
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
-#[derive(Debug, PartialEq, Clone, Copy, Default)]
+#[derive(Debug, PartialEq, Clone, Copy, Default, Serialize, Deserialize)]
 pub enum StitchingType {
     #[default]
     Plane,
@@ -14,17 +22,251 @@ pub enum StitchingType {
     Torus,
 }
 
+impl StitchingType {
+    /// Whether the (left/right, top/bottom) edges of the sculpt map are stitched together.
+    pub fn wrapped_axes(self) -> (bool, bool) {
+        match self {
+            StitchingType::Plane => (false, false),
+            StitchingType::Sphere | StitchingType::Cylinder => (true, false),
+            StitchingType::Torus => (true, true),
+        }
+    }
+}
+
+/// How the generated image is evened out across the edges a stitching type
+/// wraps. With surface coordinates the network output already meets itself
+/// there, so this only smooths what is left of the seam.
+#[derive(Debug, PartialEq, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum SeamTreatment {
+    /// Leave the network output untouched.
+    #[default]
+    None,
+    /// Blend a band on each side of the seam towards the other side.
+    Crossfade,
+    /// Space both edge lines evenly between their inner neighbours.
+    EdgeAverage,
+}
+
+impl SeamTreatment {
+    pub const ALL: [SeamTreatment; 3] = [
+        SeamTreatment::None,
+        SeamTreatment::Crossfade,
+        SeamTreatment::EdgeAverage,
+    ];
+}
+
+/// How raw network outputs are mapped into the [0, 1] cube of a sculpt map.
+#[derive(Debug, PartialEq, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum Normalization {
+    /// Stretch every channel to fill [0, 1] on its own.
+    #[default]
+    PerChannel,
+    /// Scale all channels by the same factor, keeping the shape's proportions.
+    Uniform,
+    /// Clamp outputs to [-1, 1], the range most activations produce.
+    FixedRange,
+    /// Squash outputs through a sigmoid.
+    Sigmoid,
+    /// Stretch every channel between its 2nd and 98th percentile, clipping outliers.
+    Percentile,
+}
+
+impl Normalization {
+    pub const ALL: [Normalization; 5] = [
+        Normalization::PerChannel,
+        Normalization::Uniform,
+        Normalization::FixedRange,
+        Normalization::Sigmoid,
+        Normalization::Percentile,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Normalization::PerChannel => "Per channel",
+            Normalization::Uniform => "Uniform",
+            Normalization::FixedRange => "Fixed range",
+            Normalization::Sigmoid => "Sigmoid",
+            Normalization::Percentile => "Percentile",
+        }
+    }
+}
+
+/// Width:height ratio of sculpt maps. Oblong maps keep the pixel count of a
+/// square one, trading resolution across for resolution along.
+#[derive(Debug, PartialEq, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum SculptAspect {
+    #[default]
+    Square,
+    Wide4,
+    Wide16,
+    Tall4,
+    Tall16,
+}
+
+impl SculptAspect {
+    pub const ALL: [SculptAspect; 5] = [
+        SculptAspect::Square,
+        SculptAspect::Wide4,
+        SculptAspect::Wide16,
+        SculptAspect::Tall4,
+        SculptAspect::Tall16,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SculptAspect::Square => "1:1",
+            SculptAspect::Wide4 => "4:1",
+            SculptAspect::Wide16 => "16:1",
+            SculptAspect::Tall4 => "1:4",
+            SculptAspect::Tall16 => "1:16",
+        }
+    }
+
+    /// `[width, height]` of a map with as many pixels as a `size` x `size` one.
+    pub fn dimensions(self, size: usize) -> [usize; 2] {
+        match self {
+            SculptAspect::Square => [size, size],
+            SculptAspect::Wide4 => [size * 2, (size / 2).max(2)],
+            SculptAspect::Wide16 => [size * 4, (size / 4).max(2)],
+            SculptAspect::Tall4 => [(size / 2).max(2), size * 2],
+            SculptAspect::Tall16 => [(size / 4).max(2), size * 4],
+        }
+    }
+}
+
+/// Smallest grid side offered.
+pub const MIN_GRID_SIZE: usize = 3;
+
+/// Largest grid side offered, and accepted from session files.
+pub const MAX_GRID_SIZE: usize = 10;
+
+/// Prim size the estimates assume until another one is entered.
+pub const DEFAULT_PRIM_SCALE: f32 = 5.0;
+
+/// Smallest prim edge SL allows, in metres.
+pub const MIN_PRIM_SCALE: f32 = 0.01;
+
+/// Largest prim edge SL allows, in metres.
+pub const MAX_PRIM_SCALE: f32 = 64.0;
+
+/// The SL sculpt "mirror" and "invert" flags.
+#[derive(Debug, PartialEq, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct SculptFlags {
+    /// Flip the surface along X.
+    pub mirror: bool,
+    /// Turn the surface inside out.
+    pub invert: bool,
+}
+
+/// Inputs fed to the CPPNs next to x, y and the distance from the centre.
+#[derive(Debug, PartialEq, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct InputSet {
+    /// Angle around the centre.
+    pub polar: bool,
+    /// A constant 1.
+    pub bias: bool,
+    /// |x| and |y|, which favour mirror symmetry.
+    pub absolute: bool,
+    /// sin and cos of u and v, each running once around the map.
+    pub periodic: bool,
+}
+
+/// Where the x, y and distance inputs of the CPPNs come from.
+#[derive(Debug, PartialEq, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum InputMapping {
+    /// The point on the surface the stitching type wraps the map into, so
+    /// wrapped edges meet by construction. Plane maps read x, y and the
+    /// distance from the centre.
+    #[default]
+    Surface,
+    /// x, y and the distance from the centre of the flat map, whatever the
+    /// stitching type. Sessions from before surface coordinates load as this.
+    Flat,
+    /// Like `Flat`, with x and y running once around a cosine along wrapped
+    /// axes. Edges meet, mirrored. Sessions that used the old periodic seam
+    /// treatment load as this.
+    Mirrored,
+}
+
+impl InputMapping {
+    pub const ALL: [InputMapping; 3] = [
+        InputMapping::Surface,
+        InputMapping::Flat,
+        InputMapping::Mirrored,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            InputMapping::Surface => "Surface",
+            InputMapping::Flat => "Flat map",
+            InputMapping::Mirrored => "Mirrored seams",
+        }
+    }
+}
+
+/// The session settings a genome's shape depends on. Genome files carry
+/// them, so a shared genome can be bred on as it was evolved.
+#[derive(Debug, PartialEq, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct GenomeSettings {
+    pub input_set: InputSet,
+    pub input_mapping: InputMapping,
+    pub normalization: Normalization,
+}
+
+/// An imported sculpt map, shown next to the population for comparison.
+pub struct ReferenceMap {
+    pub name: String,
+    pub field: SculptField,
+}
+
 #[derive(Resource)]
 pub struct EvoState {
-    pub genomes: Vec<NeuralNetworkTopology<3, 3>>,
+    pub genomes: Vec<Genome>,
+    /// Companion CPPN of every tile, colouring its sculpt over the same grid.
+    pub textures: Vec<Genome>,
+    /// Show the companion textures on the grid and export them with the sculpt maps.
+    pub texture_enabled: bool,
     pub fitness: Vec<f32>,
     pub generation: u64,
     pub evolution_requested: bool,
     pub debug_requested: bool,
     pub stitching_type: StitchingType,
+    pub sculpt_flags: SculptFlags,
+    pub sculpt_aspect: SculptAspect,
+    pub seam_treatment: SeamTreatment,
+    pub input_set: InputSet,
+    pub input_mapping: InputMapping,
+    pub normalization: Normalization,
+    /// Edge length of the prim box in metres the size and land impact are estimated at.
+    pub prim_scale: f32,
+    pub size_report: Option<SizeReport>,
     pub redraw_requested: bool,
     pub grid_size: usize,
     pub grid_spawn_requested: bool,
+    pub import_slot: usize,
+    pub glb_export_requested: bool,
+    pub stl_binary: bool,
+    pub stl_report: Option<MeshReport>,
+    pub dae_include_lods: bool,
+    pub sculpt_map_settings: SculptMapSettings,
+    /// Upload check of the last exported (or validated) sculpt map.
+    pub map_report: Option<MapReport>,
+    pub batch_include_mesh: bool,
+    pub batch_include_genome: bool,
+    /// Failed upload checks holding back the last batch export.
+    pub batch_map_reports: Vec<MapReport>,
+    pub reference_maps: Vec<ReferenceMap>,
+    /// Preview tiles as the SL viewer meshes them at this LOD instead of in full.
+    pub sl_lod_preview: Option<usize>,
+    /// Side of a square preview map, tiles are refined up to it over a few frames.
+    pub preview_resolution: usize,
+    /// Mesh the grid from the float network output instead of 8-bit maps.
+    pub float_geometry: bool,
+    /// 8-bit error of every tile at the export size, from the last measurement.
+    pub quantisation_reports: Vec<QuantisationReport>,
+    /// Settings the last imported genome was evolved with, until they are
+    /// taken over or dismissed.
+    pub imported_settings: Option<GenomeSettings>,
 }
 
 impl EvoState {
@@ -32,6 +274,72 @@ impl EvoState {
         self.grid_size * self.grid_size
     }
 
+    /// Index of the first tile marked as selected, if any.
+    pub fn first_selected(&self) -> Option<usize> {
+        self.fitness.iter().position(|&f| f > 0.0)
+    }
+
+    /// How networks are laid out over sculpt maps and textures.
+    pub fn image_settings(&self) -> ImageSettings {
+        ImageSettings {
+            aspect: self.sculpt_aspect,
+            stitching_type: self.stitching_type,
+            seam_treatment: self.seam_treatment,
+            inputs: self.input_set,
+            input_mapping: self.input_mapping,
+            normalization: self.normalization,
+        }
+    }
+
+    /// The settings genomes are currently bred with.
+    pub fn genome_settings(&self) -> GenomeSettings {
+        GenomeSettings {
+            input_set: self.input_set,
+            input_mapping: self.input_mapping,
+            normalization: self.normalization,
+        }
+    }
+
+    /// Breeds on with the settings of an imported genome, redrawing the grid.
+    pub fn apply_genome_settings(&mut self, settings: GenomeSettings) {
+        self.input_set = settings.input_set;
+        self.input_mapping = settings.input_mapping;
+        self.normalization = settings.normalization;
+        self.redraw_requested = true;
+    }
+
+    /// Resolution tiles are first meshed at, before they are refined to the
+    /// preview resolution. The SL LOD preview meshes the export map instead,
+    /// so there is nothing to refine.
+    pub fn coarse_resolution(&self) -> usize {
+        if self.sl_lod_preview.is_some() {
+            self.preview_resolution
+        } else {
+            self.preview_resolution
+                .min(generator::COARSE_PREVIEW_RESOLUTION)
+        }
+    }
+
+    /// Indices of all tiles marked as selected.
+    pub fn selected_indices(&self) -> Vec<usize> {
+        self.fitness
+            .iter()
+            .enumerate()
+            .filter(|&(_, &f)| f > 0.0)
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// Drops the checked sculpt maps and tile reports once the tiles they
+    /// were made from change.
+    pub fn clear_map_reports(&mut self) {
+        self.map_report = None;
+        self.batch_map_reports.clear();
+        self.quantisation_reports.clear();
+        self.size_report = None;
+        self.stl_report = None;
+    }
+
     pub fn resize_grid(&mut self, new_size: usize) {
         if self.grid_size == new_size {
             return;
@@ -43,20 +351,48 @@ impl EvoState {
 
         if self.genomes.len() < target_pop {
             let additional = target_pop - self.genomes.len();
+            let live = generator::live_inputs(self.image_settings());
             for _ in 0..additional {
-                let mut genome = NeuralNetworkTopology::new(0.2, 3, &mut rng);
-                Self::diversify_genome(&mut genome, &mut rng);
-                self.genomes.push(genome);
+                self.genomes.push(Self::random_genome(&live, &mut rng));
+                self.textures.push(Self::random_genome(&live, &mut rng));
             }
         } else {
             self.genomes.truncate(target_pop);
+            self.textures.truncate(target_pop);
         }
 
         self.fitness.resize(target_pop, 0.0);
+        self.import_slot = self.import_slot.min(target_pop - 1);
+        self.clear_map_reports();
         self.grid_spawn_requested = true;
     }
 
-    fn diversify_genome(genome: &mut NeuralNetworkTopology<3, 3>, rng: &mut impl Rng) {
+    /// A fresh genome reading only from the `live` inputs, see `generator::live_inputs`.
+    pub fn random_genome(live: &[usize], rng: &mut impl Rng) -> Genome {
+        let mut genome = NeuralNetworkTopology::new(0.2, 3, rng);
+        Self::connect_live_inputs(&mut genome, live, rng);
+        Self::diversify_genome(&mut genome, rng);
+        genome
+    }
+
+    /// New genomes connect their outputs to any input, most of which read
+    /// zero with the default input set. Moves each output's connections over
+    /// to distinct live inputs, weights and all, so no channel starts flat.
+    fn connect_live_inputs(genome: &mut Genome, live: &[usize], rng: &mut impl Rng) {
+        for neuron_arc in &genome.output_layer {
+            let mut neuron = neuron_arc.write().unwrap();
+            let mut sources = live.to_vec();
+            sources.shuffle(rng);
+            let weights: Vec<f32> = neuron.inputs.iter().map(|&(_, weight)| weight).collect();
+            neuron.inputs = sources
+                .into_iter()
+                .zip(weights)
+                .map(|(input, weight)| (NeuronLocation::Input(input), weight))
+                .collect();
+        }
+    }
+
+    fn diversify_genome(genome: &mut Genome, rng: &mut impl Rng) {
         let output_activations = activation_fn! {
             sigmoid => ActivationScope::OUTPUT,
             relu => ActivationScope::OUTPUT,
@@ -88,24 +424,74 @@ impl Default for EvoState {
         let grid_size = 4;
         let pop_size = grid_size * grid_size;
 
-        let mut genomes: Vec<NeuralNetworkTopology<3, 3>> = (0..pop_size)
-            .map(|_| NeuralNetworkTopology::new(0.2, 3, &mut rng))
-            .collect();
-
-        for genome in &mut genomes {
-            Self::diversify_genome(genome, &mut rng);
-        }
-
-        Self {
-            genomes,
+        let mut state = Self {
+            genomes: Vec::new(),
+            textures: Vec::new(),
+            texture_enabled: false,
             fitness: vec![0.0; pop_size],
             generation: 0,
             evolution_requested: false,
             debug_requested: false,
             stitching_type: StitchingType::default(),
+            sculpt_flags: SculptFlags::default(),
+            sculpt_aspect: SculptAspect::default(),
+            seam_treatment: SeamTreatment::default(),
+            input_set: InputSet::default(),
+            input_mapping: InputMapping::default(),
+            normalization: Normalization::default(),
+            prim_scale: DEFAULT_PRIM_SCALE,
+            size_report: None,
             redraw_requested: true,
             grid_size,
             grid_spawn_requested: true,
+            import_slot: 0,
+            glb_export_requested: false,
+            stl_binary: true,
+            stl_report: None,
+            dae_include_lods: true,
+            sculpt_map_settings: SculptMapSettings::default(),
+            map_report: None,
+            batch_include_mesh: false,
+            batch_include_genome: true,
+            batch_map_reports: Vec::new(),
+            reference_maps: Vec::new(),
+            sl_lod_preview: None,
+            preview_resolution: generator::PREVIEW_RESOLUTION,
+            float_geometry: false,
+            quantisation_reports: Vec::new(),
+            imported_settings: None,
+        };
+
+        let live = generator::live_inputs(state.image_settings());
+        state.genomes = (0..pop_size)
+            .map(|_| Self::random_genome(&live, &mut rng))
+            .collect();
+        state.textures = (0..pop_size)
+            .map(|_| Self::random_genome(&live, &mut rng))
+            .collect();
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn random_genomes_read_only_live_inputs() {
+        let mut rng = thread_rng();
+        for _ in 0..20 {
+            let genome = EvoState::random_genome(&[0, 1, 2], &mut rng);
+            for neuron in &genome.output_layer {
+                let inputs = &neuron.read().unwrap().inputs;
+                assert!(!inputs.is_empty());
+                assert!(inputs.len() <= 3);
+                assert!(
+                    inputs
+                        .iter()
+                        .all(|(location, _)| matches!(location, NeuronLocation::Input(0..=2)))
+                );
+            }
         }
     }
 }
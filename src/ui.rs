@@ -1,12 +1,17 @@
-use crate::{Selectable, generator, io, sculpt, state};
+use crate::export::sculpt_map::{self, ResampleFilter, SculptMapFormat};
+use crate::field::SculptField;
+use crate::{ReferenceTile, Selectable, bake, export, generator, io, sculpt, session, state};
 use bevy::asset::RenderAssetUsages;
 use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
 use bevy_egui::{EguiContexts, egui};
 use bevy_panorbit_camera::PanOrbitCamera;
-use image::{DynamicImage, ImageBuffer, Rgba, imageops::FilterType};
-use std::io::Cursor;
 
-pub fn ui_system(mut contexts: EguiContexts, mut evo_state: ResMut<state::EvoState>) {
+pub fn ui_system(
+    mut contexts: EguiContexts,
+    mut evo_state: ResMut<state::EvoState>,
+    loaded_files: Res<io::LoadedFiles>,
+) {
     if let Ok(ctx) = contexts.ctx_mut() {
         egui::Window::new("Evo-Sculptor Controls").show(ctx, |ui| {
             ui.heading(format!("Generation: {}", evo_state.generation));
@@ -18,7 +23,7 @@ pub fn ui_system(mut contexts: EguiContexts, mut evo_state: ResMut<state::EvoSta
                 egui::ComboBox::from_id_salt("grid_size_combo")
                     .selected_text(format!("{}x{}", current_size, current_size))
                     .show_ui(ui, |ui| {
-                        for size in 3..=10 {
+                        for size in state::MIN_GRID_SIZE..=state::MAX_GRID_SIZE {
                             ui.selectable_value(
                                 &mut current_size,
                                 size,
@@ -39,55 +44,468 @@ pub fn ui_system(mut contexts: EguiContexts, mut evo_state: ResMut<state::EvoSta
                 }
                 if ui.button("Reset Population").clicked() {
                     let current_size = evo_state.grid_size;
+                    let reference_maps = std::mem::take(&mut evo_state.reference_maps);
                     *evo_state = state::EvoState::default();
+                    evo_state.reference_maps = reference_maps;
                     evo_state.resize_grid(current_size);
                 }
                 //if ui.button("Log Activations").clicked() {
                 //    evo_state.debug_requested = true;
                 //}
                 if ui.button("Export Selected").clicked() {
-                    // 1. Find the selected genome/image
-                    if let Some((index, _)) = evo_state
-                        .fitness
-                        .iter()
-                        .enumerate()
-                        .find(|&(_, &f)| f > 0.0)
-                    {
-                        let topology = &evo_state.genomes[index];
-
-                        // 2. Re-generate the image (or retrieve it if you cached it)
-                        let bevy_image = crate::generator::generate_image_from_topology(topology);
-
-                        // 3. Convert Bevy Image to TGA bytes
-                        // Note: ColorImage pixels are [r, g, b, a] bytes
-                        let width = bevy_image.size[0] as u32;
-                        let height = bevy_image.size[1] as u32;
+                    // 1. Find the selected genome
+                    if let Some(index) = evo_state.first_selected() {
+                        // 2. Re-generate the sculpt map and check it before saving
+                        let sculpt_map = export::sculpt_map::render_sculpt_map(
+                            &evo_state.genomes[index],
+                            &evo_state.sculpt_map_settings,
+                            evo_state.image_settings(),
+                        );
+                        let report = export::validate::validate_sculpt_map(
+                            sculpt_map,
+                            evo_state.stitching_type,
+                            index,
+                        );
 
-                        let raw_data = bevy_image.as_raw().to_vec();
-
-                        if let Some(buffer) =
-                            ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, raw_data)
-                        {
-                            // Convert to DynamicImage for easy resizing
-                            let dynamic_image = DynamicImage::ImageRgba8(buffer);
-
-                            // Resize to 64x64 using Nearest Neighbor (No interpolation/blur)
-                            let resized_image =
-                                dynamic_image.resize_exact(64, 64, FilterType::Nearest);
-
-                            let mut bytes: Vec<u8> = Vec::new();
-
-                            // Write TGA to the byte vector
-                            resized_image
-                                .write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Tga)
-                                .unwrap();
-
-                            // 4. Call our cross-platform saver
-                            io::save_sculpt_map(bytes, &format!("sculpt_genome_{}.tga", index));
+                        // 3. Broken maps wait for "Export Anyway" in the report
+                        if report.is_valid() {
+                            save_sculpt_map(&evo_state, index, &report.image);
                         }
+                        evo_state.map_report = Some(report);
+                    }
+                }
+                if ui.button("Export OBJ").clicked() {
+                    // Export at unit size, like a 1m prim in-world
+                    if let Some((index, sculpt_data)) = selected_sculpt_mesh(&evo_state, 1.0) {
+                        let name = export::tile_file_stem(evo_state.generation, index);
+                        let bytes = export::obj::write_obj(&sculpt_data, &name);
+                        io::save_file(bytes, &format!("{}.obj", name), io::OBJ_FILTER);
+                    }
+                }
+                if ui.button("Export glTF").clicked() {
+                    evo_state.glb_export_requested = true;
+                }
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Export Genome").clicked()
+                    && let Some(index) = evo_state.first_selected()
+                {
+                    let bytes = session::genome_to_bytes(&evo_state, index);
+                    io::save_file(
+                        bytes,
+                        &format!(
+                            "{}.genome",
+                            export::tile_file_stem(evo_state.generation, index)
+                        ),
+                        io::GENOME_FILTER,
+                    );
+                }
+                let last_slot = evo_state.get_population_size() - 1;
+                ui.add(
+                    egui::DragValue::new(&mut evo_state.import_slot)
+                        .range(0..=last_slot)
+                        .prefix("Slot: "),
+                );
+                if ui.button("Import Genome").clicked() {
+                    io::open_file(
+                        &loaded_files,
+                        io::LoadPurpose::Genome {
+                            slot: evo_state.import_slot,
+                        },
+                        io::GENOME_FILTER,
+                    );
+                }
+            });
+            // A genome bred with other settings grows into another shape
+            if let Some(settings) = evo_state.imported_settings
+                && settings != evo_state.genome_settings()
+            {
+                ui.colored_label(
+                    egui::Color32::YELLOW,
+                    format!(
+                        "The imported genome was bred with other CPPN inputs ({}, {})",
+                        settings.input_mapping.label(),
+                        settings.normalization.label()
+                    ),
+                );
+                ui.horizontal(|ui| {
+                    if ui.button("Use Its Settings").clicked() {
+                        evo_state.apply_genome_settings(settings);
+                        evo_state.imported_settings = None;
+                    }
+                    if ui.button("Keep Current").clicked() {
+                        evo_state.imported_settings = None;
+                    }
+                });
+            }
+            ui.horizontal(|ui| {
+                if ui.button("Save Session").clicked() {
+                    let bytes = session::session_to_bytes(&evo_state);
+                    io::save_file(
+                        bytes,
+                        &format!("session_gen_{}.evosession", evo_state.generation),
+                        io::SESSION_FILTER,
+                    );
+                }
+                if ui.button("Load Session").clicked() {
+                    io::open_file(&loaded_files, io::LoadPurpose::Session, io::SESSION_FILTER);
+                }
+            });
+            ui.separator();
+            ui.heading("Sculpt Map");
+            let previous_settings = evo_state.sculpt_map_settings;
+            let previous_aspect = evo_state.sculpt_aspect;
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_salt("aspect_combo")
+                    .selected_text(evo_state.sculpt_aspect.label())
+                    .show_ui(ui, |ui| {
+                        for aspect in state::SculptAspect::ALL {
+                            ui.selectable_value(
+                                &mut evo_state.sculpt_aspect,
+                                aspect,
+                                aspect.label(),
+                            );
+                        }
+                    });
+                let aspect = evo_state.sculpt_aspect;
+                let settings = &mut evo_state.sculpt_map_settings;
+                ui.radio_value(&mut settings.format, SculptMapFormat::Tga, "TGA");
+                ui.radio_value(&mut settings.format, SculptMapFormat::Png, "PNG");
+                let [width, height] = aspect.dimensions(settings.size);
+                egui::ComboBox::from_id_salt("export_size_combo")
+                    .selected_text(format!("{}x{}", width, height))
+                    .show_ui(ui, |ui| {
+                        for size in sculpt_map::EXPORT_SIZES {
+                            let [width, height] = aspect.dimensions(size);
+                            ui.selectable_value(
+                                &mut settings.size,
+                                size,
+                                format!("{}x{}", width, height),
+                            );
+                        }
+                    });
+                egui::ComboBox::from_id_salt("export_filter_combo")
+                    .selected_text(format!("{:?}", settings.filter))
+                    .show_ui(ui, |ui| {
+                        for filter in ResampleFilter::ALL {
+                            ui.selectable_value(
+                                &mut settings.filter,
+                                filter,
+                                format!("{:?}", filter),
+                            );
+                        }
+                    });
+                ui.checkbox(&mut settings.reevaluate, "Re-evaluate at size");
+                ui.checkbox(&mut settings.sidecar, "JSON Sidecar");
+                if ui
+                    .checkbox(&mut evo_state.texture_enabled, "Texture")
+                    .changed()
+                {
+                    evo_state.redraw_requested = true;
+                }
+            });
+            // The SL LOD preview meshes the exported map, so it follows these settings
+            if evo_state.sl_lod_preview.is_some()
+                && evo_state.sculpt_map_settings != previous_settings
+            {
+                evo_state.redraw_requested = true;
+            }
+            if evo_state.sculpt_aspect != previous_aspect {
+                evo_state.redraw_requested = true;
+            }
+            ui.horizontal(|ui| {
+                if ui.button("Validate").clicked()
+                    && let Some(index) = evo_state.first_selected()
+                {
+                    let sculpt_map = export::sculpt_map::render_sculpt_map(
+                        &evo_state.genomes[index],
+                        &evo_state.sculpt_map_settings,
+                        evo_state.image_settings(),
+                    );
+                    evo_state.map_report = Some(export::validate::validate_sculpt_map(
+                        sculpt_map,
+                        evo_state.stitching_type,
+                        index,
+                    ));
+                }
+                if evo_state.map_report.is_some() && ui.button("Dismiss").clicked() {
+                    evo_state.map_report = None;
+                }
+            });
+            let mut export_anyway = false;
+            if let Some(report) = &evo_state.map_report {
+                ui.label(format!(
+                    "Tile {} ({}x{}): {}",
+                    report.index,
+                    report.width,
+                    report.height,
+                    if report.is_valid() {
+                        "ready to upload".to_string()
+                    } else {
+                        format!("{} problems", report.problems.len())
                     }
+                ));
+                for problem in &report.problems {
+                    ui.colored_label(egui::Color32::YELLOW, problem);
+                }
+                if !report.is_valid() && ui.button("Export Anyway").clicked() {
+                    save_sculpt_map(&evo_state, report.index, &report.image);
+                    export_anyway = true;
+                }
+            }
+            if export_anyway {
+                evo_state.map_report = None;
+            }
+            ui.horizontal(|ui| {
+                if ui.button("Import Reference").clicked() {
+                    io::open_file(
+                        &loaded_files,
+                        io::LoadPurpose::ReferenceMap,
+                        io::SCULPT_MAP_FILTER,
+                    );
+                }
+                if ui.button("Bake OBJ").clicked() {
+                    io::open_file(&loaded_files, io::LoadPurpose::BakeMesh, io::OBJ_FILTER);
+                }
+                if !evo_state.reference_maps.is_empty()
+                    && ui
+                        .button(format!(
+                            "Clear References ({})",
+                            evo_state.reference_maps.len()
+                        ))
+                        .clicked()
+                {
+                    evo_state.reference_maps.clear();
+                    evo_state.grid_spawn_requested = true;
                 }
             });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut evo_state.batch_include_mesh, "With OBJ");
+                ui.checkbox(&mut evo_state.batch_include_genome, "With Genome");
+                if ui.button("Export All Selected").clicked() {
+                    save_batch(&mut evo_state, false);
+                }
+            });
+            if !evo_state.batch_map_reports.is_empty() {
+                for report in &evo_state.batch_map_reports {
+                    ui.label(format!(
+                        "Tile {} ({}x{}): {} problems",
+                        report.index,
+                        report.width,
+                        report.height,
+                        report.problems.len()
+                    ));
+                    for problem in &report.problems {
+                        ui.colored_label(egui::Color32::YELLOW, problem);
+                    }
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("Export All Anyway").clicked() {
+                        save_batch(&mut evo_state, true);
+                    }
+                    if ui.button("Dismiss").clicked() {
+                        evo_state.batch_map_reports.clear();
+                    }
+                });
+            }
+            ui.separator();
+            ui.heading("Second Life Mesh");
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut evo_state.dae_include_lods, "Include LODs");
+                if ui.button("Export DAE").clicked()
+                    && let Some(index) = evo_state.first_selected()
+                {
+                    let field = generator::generate_field_from_topology(
+                        &evo_state.genomes[index],
+                        evo_state.image_settings(),
+                        evo_state.preview_resolution,
+                    );
+                    let stem = export::tile_file_stem(evo_state.generation, index);
+                    let mut files = export::collada::write_dae_with_lods(
+                        &field,
+                        evo_state.stitching_type,
+                        evo_state.sculpt_flags,
+                        &stem,
+                        evo_state.dae_include_lods,
+                    );
+                    // One dialog (or download) for all LODs
+                    if files.len() == 1 {
+                        let (name, bytes) = files.remove(0);
+                        io::save_file(bytes, &name, io::DAE_FILTER);
+                    } else {
+                        io::save_files(files, &format!("{}_dae.zip", stem));
+                    }
+                }
+            });
+            ui.separator();
+            ui.heading("3D Print");
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut evo_state.stl_binary, "Binary STL");
+                // STL has no units, slicers read it as millimetres
+                if ui.button("Check Mesh").clicked()
+                    && let Some((index, sculpt_data)) = selected_sculpt_mesh(&evo_state, 50.0)
+                {
+                    evo_state.stl_report = Some(export::stl::analyze_mesh(&sculpt_data, index));
+                }
+                if ui.button("Export STL").clicked()
+                    && let Some((index, sculpt_data)) = selected_sculpt_mesh(&evo_state, 50.0)
+                {
+                    let name = export::tile_file_stem(evo_state.generation, index);
+                    let bytes = if evo_state.stl_binary {
+                        export::stl::write_binary_stl(&sculpt_data, &name)
+                    } else {
+                        export::stl::write_ascii_stl(&sculpt_data, &name)
+                    };
+                    evo_state.stl_report = Some(export::stl::analyze_mesh(&sculpt_data, index));
+                    io::save_file(bytes, &format!("{}.stl", name), io::STL_FILTER);
+                }
+            });
+            if let Some(report) = &evo_state.stl_report {
+                ui.label(format!(
+                    "Tile {} ({:?}): {} triangles",
+                    report.index, report.stitching_type, report.triangles
+                ));
+                ui.label(format!(
+                    "Closed: {} (expected: {}), Manifold: {}",
+                    if report.is_closed() { "yes" } else { "no" },
+                    if report.expects_closed() { "yes" } else { "no" },
+                    if report.is_manifold() { "yes" } else { "no" },
+                ));
+                ui.label(format!(
+                    "Open edges: {}, Non-manifold edges: {}, Degenerate triangles: {}",
+                    report.open_edges, report.non_manifold_edges, report.degenerate_triangles
+                ));
+            }
+            ui.separator();
+            ui.heading("Size & Land Impact");
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::DragValue::new(&mut evo_state.prim_scale)
+                        .range(state::MIN_PRIM_SCALE..=state::MAX_PRIM_SCALE)
+                        .speed(0.05)
+                        .prefix("Scale: ")
+                        .suffix(" m"),
+                );
+                if ui.button("Estimate").clicked()
+                    && let Some(index) = evo_state.first_selected()
+                {
+                    let field = generator::generate_field_from_topology(
+                        &evo_state.genomes[index],
+                        evo_state.image_settings(),
+                        evo_state.preview_resolution,
+                    );
+                    evo_state.size_report = Some(export::land_impact::estimate_size(
+                        &field,
+                        evo_state.stitching_type,
+                        evo_state.sculpt_flags,
+                        evo_state.prim_scale,
+                        index,
+                    ));
+                }
+            });
+            if let Some(report) = &evo_state.size_report {
+                let [x, y, z] = report.bounds;
+                ui.label(format!(
+                    "Tile {} at {:.2} m: {:.2} x {:.2} x {:.2} m",
+                    report.index, report.scale, x, y, z
+                ));
+                ui.label(format!(
+                    "Sculpt triangles, LOD 3..0: {:?}",
+                    report.sculpt_triangles.iter().rev().collect::<Vec<_>>()
+                ));
+                ui.label(format!(
+                    "Mesh triangles, high..lowest: {:?}",
+                    report.mesh_triangles.iter().rev().collect::<Vec<_>>()
+                ));
+                ui.label(format!(
+                    "Estimated mesh land impact: {} (download weight {:.2})",
+                    report.land_impact(),
+                    report.streaming_cost
+                ));
+            }
+            ui.separator();
+            ui.heading("Preview Resolution");
+            // No redraw needed, update_meshes_system refines the tiles that differ
+            ui.horizontal(|ui| {
+                let aspect = evo_state.sculpt_aspect;
+                let [width, height] = aspect.dimensions(evo_state.preview_resolution);
+                egui::ComboBox::from_id_salt("preview_resolution_combo")
+                    .selected_text(format!("{}x{}", width, height))
+                    .show_ui(ui, |ui| {
+                        for resolution in generator::PREVIEW_RESOLUTIONS {
+                            let [width, height] = aspect.dimensions(resolution);
+                            ui.selectable_value(
+                                &mut evo_state.preview_resolution,
+                                resolution,
+                                format!("{}x{}", width, height),
+                            );
+                        }
+                    });
+            });
+            ui.separator();
+            ui.heading("SL LOD Preview");
+            ui.horizontal(|ui| {
+                let previous_lod = evo_state.sl_lod_preview;
+                ui.radio_value(&mut evo_state.sl_lod_preview, None, "Full");
+                for lod in (0..sculpt::SL_LOD_SIDES.len()).rev() {
+                    ui.radio_value(
+                        &mut evo_state.sl_lod_preview,
+                        Some(lod),
+                        format!("LOD {}", lod),
+                    );
+                }
+                if evo_state.sl_lod_preview != previous_lod {
+                    evo_state.redraw_requested = true;
+                }
+            });
+            ui.separator();
+            ui.heading("Quantisation");
+            ui.horizontal(|ui| {
+                if ui
+                    .checkbox(&mut evo_state.float_geometry, "Float Geometry")
+                    .changed()
+                {
+                    evo_state.redraw_requested = true;
+                }
+                if ui.button("Measure").clicked() {
+                    // At the export size, where the 8-bit map is what SL receives
+                    let settings = evo_state.image_settings();
+                    let [width, height] = settings
+                        .aspect
+                        .dimensions(evo_state.sculpt_map_settings.size);
+                    let reports = evo_state
+                        .genomes
+                        .iter()
+                        .enumerate()
+                        .map(|(index, genome)| {
+                            let field = generator::generate_field(genome, width, height, settings);
+                            export::quantisation::measure_quantisation(
+                                &field,
+                                evo_state.prim_scale,
+                                index,
+                            )
+                        })
+                        .collect();
+                    evo_state.quantisation_reports = reports;
+                }
+                if !evo_state.quantisation_reports.is_empty() && ui.button("Dismiss").clicked() {
+                    evo_state.quantisation_reports.clear();
+                }
+            });
+            for report in &evo_state.quantisation_reports {
+                let text = format!(
+                    "Tile {}: {:.1} mm RMS, {:.1} mm max, {:.0}% of steps flattened",
+                    report.index,
+                    report.rms_error * 1000.0,
+                    report.max_error * 1000.0,
+                    report.flattened_steps * 100.0
+                );
+                if report.loses_detail() {
+                    ui.colored_label(egui::Color32::YELLOW, text);
+                } else {
+                    ui.label(text);
+                }
+            }
             ui.separator();
             ui.heading("Stitching Type");
             ui.horizontal(|ui| {
@@ -132,10 +550,234 @@ pub fn ui_system(mut contexts: EguiContexts, mut evo_state: ResMut<state::EvoSta
                     evo_state.redraw_requested = true;
                 }
             });
+            ui.horizontal(|ui| {
+                let previous_seam = evo_state.seam_treatment;
+                egui::ComboBox::from_id_salt("seam_combo")
+                    .selected_text(format!("Seams: {:?}", evo_state.seam_treatment))
+                    .show_ui(ui, |ui| {
+                        for seam in state::SeamTreatment::ALL {
+                            ui.selectable_value(
+                                &mut evo_state.seam_treatment,
+                                seam,
+                                format!("{:?}", seam),
+                            );
+                        }
+                    });
+                if evo_state.seam_treatment != previous_seam {
+                    evo_state.redraw_requested = true;
+                }
+                if ui
+                    .checkbox(&mut evo_state.sculpt_flags.mirror, "Mirror")
+                    .changed()
+                {
+                    evo_state.redraw_requested = true;
+                }
+                if ui
+                    .checkbox(&mut evo_state.sculpt_flags.invert, "Invert")
+                    .changed()
+                {
+                    evo_state.redraw_requested = true;
+                }
+            });
+            ui.separator();
+            ui.heading("CPPN Inputs");
+            ui.horizontal(|ui| {
+                let previous_inputs = evo_state.input_set;
+                let inputs = &mut evo_state.input_set;
+                ui.label("x, y, distance");
+                ui.checkbox(&mut inputs.polar, "Angle");
+                ui.checkbox(&mut inputs.bias, "Bias");
+                ui.checkbox(&mut inputs.absolute, "|x|, |y|");
+                ui.checkbox(&mut inputs.periodic, "sin/cos u, v");
+                if evo_state.input_set != previous_inputs {
+                    evo_state.redraw_requested = true;
+                }
+            });
+            ui.horizontal(|ui| {
+                let previous_mapping = evo_state.input_mapping;
+                egui::ComboBox::from_id_salt("input_mapping_combo")
+                    .selected_text(format!("Coordinates: {}", evo_state.input_mapping.label()))
+                    .show_ui(ui, |ui| {
+                        for mapping in state::InputMapping::ALL {
+                            ui.selectable_value(
+                                &mut evo_state.input_mapping,
+                                mapping,
+                                mapping.label(),
+                            );
+                        }
+                    });
+                if evo_state.input_mapping != previous_mapping {
+                    evo_state.redraw_requested = true;
+                }
+            });
+            ui.horizontal(|ui| {
+                let previous_normalization = evo_state.normalization;
+                egui::ComboBox::from_id_salt("normalization_combo")
+                    .selected_text(format!("Outputs: {}", evo_state.normalization.label()))
+                    .show_ui(ui, |ui| {
+                        for normalization in state::Normalization::ALL {
+                            ui.selectable_value(
+                                &mut evo_state.normalization,
+                                normalization,
+                                normalization.label(),
+                            );
+                        }
+                    });
+                if evo_state.normalization != previous_normalization {
+                    evo_state.redraw_requested = true;
+                }
+            });
         });
     }
 }
 
+/// Encodes a rendered sculpt map and saves it with the current settings,
+/// followed by its companion texture when textures are on.
+fn save_sculpt_map(evo_state: &state::EvoState, index: usize, sculpt_map: &image::DynamicImage) {
+    let settings = evo_state.sculpt_map_settings;
+    let bytes = export::sculpt_map::encode_image(sculpt_map, settings.format);
+    let stem = export::tile_file_stem(evo_state.generation, index);
+    let name = format!("{}.{}", stem, settings.format.extension());
+
+    // With a texture there is more than one file, ask for them all at once
+    if evo_state.texture_enabled {
+        let mut files = vec![(name, bytes)];
+        if settings.sidecar {
+            files.push((
+                format!("{}.json", stem),
+                export::tile_sidecar(evo_state, index),
+            ));
+        }
+        files.push(export::tile_texture(evo_state, index));
+        io::save_files(files, &format!("{}.zip", stem));
+    } else if settings.sidecar {
+        io::save_file_with_sidecar(
+            bytes,
+            &name,
+            settings.format.file_filter(),
+            export::tile_sidecar(evo_state, index),
+        );
+    } else {
+        io::save_file(bytes, &name, settings.format.file_filter());
+    }
+}
+
+/// Saves every selected tile in one go, unless a sculpt map fails validation
+/// and `include_invalid` is not set; those reports are kept to show instead.
+fn save_batch(evo_state: &mut state::EvoState, include_invalid: bool) {
+    match export::batch_export_files(evo_state, include_invalid) {
+        Ok(files) => {
+            evo_state.batch_map_reports.clear();
+            if !files.is_empty() {
+                io::save_files(
+                    files,
+                    &format!("sculpts_gen{:03}.zip", evo_state.generation),
+                );
+            }
+        }
+        Err(reports) => evo_state.batch_map_reports = reports,
+    }
+}
+
+/// Meshes the first selected tile at the given size.
+fn selected_sculpt_mesh(
+    evo_state: &state::EvoState,
+    size: f32,
+) -> Option<(usize, sculpt::SculptMeshData)> {
+    let index = evo_state.first_selected()?;
+    let field = generator::generate_field_from_topology(
+        &evo_state.genomes[index],
+        evo_state.image_settings(),
+        evo_state.preview_resolution,
+    );
+    let sculpt_data = sculpt::create_sculpt_mesh(
+        &field,
+        size,
+        evo_state.stitching_type,
+        evo_state.sculpt_flags,
+    );
+    Some((index, sculpt_data))
+}
+
+pub fn handle_loaded_files(
+    loaded_files: Res<io::LoadedFiles>,
+    mut evo_state: ResMut<state::EvoState>,
+) {
+    for file in loaded_files.drain() {
+        match file.purpose {
+            io::LoadPurpose::Session => {
+                if let Err(e) = session::load_session(&mut evo_state, &file.data) {
+                    eprintln!("Failed to load session {}: {}", file.name, e);
+                } else {
+                    info!("Loaded session {}", file.name);
+                }
+            }
+            io::LoadPurpose::ReferenceMap => {
+                match export::sculpt_map::decode_sculpt_map(&file.data) {
+                    Ok(field) => {
+                        evo_state.reference_maps.push(state::ReferenceMap {
+                            name: file.name,
+                            field,
+                        });
+                        evo_state.grid_spawn_requested = true;
+                    }
+                    Err(e) => eprintln!("Failed to import sculpt map {}: {}", file.name, e),
+                }
+            }
+            io::LoadPurpose::BakeMesh => match export::obj::read_obj(&file.data) {
+                Ok(mesh) => {
+                    let settings = evo_state.sculpt_map_settings;
+                    let [width, height] = evo_state.sculpt_aspect.dimensions(settings.size);
+                    let field =
+                        bake::bake_sculpt_map(&mesh, evo_state.stitching_type, width, height);
+
+                    let stem = file
+                        .name
+                        .rsplit_once('.')
+                        .map_or(&*file.name, |(stem, _)| stem);
+                    let bytes = export::sculpt_map::encode_image(
+                        &export::sculpt_map::field_to_dynamic(&field),
+                        settings.format,
+                    );
+                    io::save_file(
+                        bytes,
+                        &format!("{}.{}", stem, settings.format.extension()),
+                        settings.format.file_filter(),
+                    );
+
+                    // Show the bake next to the population to compare against
+                    evo_state.reference_maps.push(state::ReferenceMap {
+                        name: file.name,
+                        field,
+                    });
+                    evo_state.grid_spawn_requested = true;
+                }
+                Err(e) => eprintln!("Failed to bake mesh {}: {}", file.name, e),
+            },
+            io::LoadPurpose::Genome { slot } => {
+                if slot >= evo_state.genomes.len() {
+                    eprintln!("Grid slot {} no longer exists", slot);
+                    continue;
+                }
+                match session::genome_from_bytes(&file.data) {
+                    Ok((genome, texture, settings)) => {
+                        evo_state.genomes[slot] = genome;
+                        if let Some(texture) = texture {
+                            evo_state.textures[slot] = texture;
+                        }
+                        evo_state.fitness[slot] = 0.0;
+                        evo_state.imported_settings = Some(settings);
+                        evo_state.clear_map_reports();
+                        evo_state.grid_spawn_requested = true;
+                        info!("Imported genome {} into slot {}", file.name, slot);
+                    }
+                    Err(e) => eprintln!("Failed to import genome {}: {}", file.name, e),
+                }
+            }
+        }
+    }
+}
+
 pub fn setup_camera_lights(mut commands: Commands) {
     // Add a primary directional light
     commands.spawn((
@@ -163,13 +805,17 @@ pub fn setup_camera_lights(mut commands: Commands) {
     ));
 }
 
+/// Evolved tiles and reference tiles, both belong to the grid.
+type GridTileFilter = Or<(With<Selectable>, With<ReferenceTile>)>;
+
 pub fn spawn_grid_system(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    mut images: ResMut<Assets<Image>>,
     mut evo_state: ResMut<state::EvoState>,
     // Query to delete old entities
-    grid_query: Query<Entity, With<Selectable>>,
+    grid_query: Query<Entity, GridTileFilter>,
 ) {
     if evo_state.grid_spawn_requested {
         // 1. Despawn existing grid entities
@@ -180,59 +826,132 @@ pub fn spawn_grid_system(
         // 2. Spawn new grid
         let grid_size = evo_state.grid_size;
         let spacing = 10.0;
-
-        for (i, topology) in evo_state.genomes.iter().enumerate() {
-            // Recalculate position to center the grid regardless of size
+        // Recalculate position to center the grid regardless of size
+        let position = |i: usize| {
             let x = (i % grid_size) as f32 * spacing - (spacing * (grid_size - 1) as f32) / 2.0;
             let z = (i / grid_size) as f32 * spacing - (spacing * (grid_size - 1) as f32) / 2.0;
+            Transform::from_xyz(x, 0.0, z)
+        };
 
-            let image = generator::generate_image_from_topology(topology);
-            let sculpt_data = sculpt::create_sculpt_mesh(&image, 5.0, evo_state.stitching_type);
-
-            let mut mesh = Mesh::new(
-                bevy::mesh::PrimitiveTopology::TriangleList,
-                RenderAssetUsages::RENDER_WORLD | RenderAssetUsages::MAIN_WORLD,
-            );
-            mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, sculpt_data.vertices);
-            mesh.insert_indices(sculpt_data.indices);
-            mesh.compute_smooth_normals();
+        // Tiles start out coarse, update_meshes_system refines them
+        let resolution = evo_state.coarse_resolution();
+        for (i, topology) in evo_state.genomes.iter().enumerate() {
+            let sculpt_data = sculpt::create_genome_preview_mesh(topology, &evo_state, resolution);
 
-            let handle = meshes.add(mesh);
-            let material_handle = materials.add(StandardMaterial {
-                base_color: Color::srgb(0.8, 0.7, 0.6),
+            let handle = meshes.add(build_mesh(sculpt_data));
+            let mut material = StandardMaterial {
                 metallic: 0.2,
                 perceptual_roughness: 0.6,
                 ..default()
-            });
+            };
+            apply_tile_texture(&mut material, &mut images, &evo_state, i);
+            let material_handle = materials.add(material);
 
             commands
                 .spawn((
                     Mesh3d(handle),
                     MeshMaterial3d(material_handle),
-                    Transform::from_xyz(x, 0.0, z),
+                    position(i),
                     Selectable {
                         index: i,
-                        is_selected: false,
+                        is_selected: evo_state.fitness.get(i).is_some_and(|&f| f > 0.0),
+                        resolution,
                     },
                 ))
                 .observe(on_click_mesh);
         }
 
+        // 3. Spawn read-only reference tiles in the rows after the grid
+        let first_reference = grid_size * grid_size;
+        for (i, reference) in evo_state.reference_maps.iter().enumerate() {
+            let sculpt_data = sculpt::create_preview_mesh(&reference.field, &evo_state);
+
+            let handle = meshes.add(build_mesh(sculpt_data));
+            let material_handle = materials.add(StandardMaterial {
+                base_color: Color::srgb(0.55, 0.65, 0.75),
+                metallic: 0.2,
+                perceptual_roughness: 0.6,
+                ..default()
+            });
+
+            commands.spawn((
+                Mesh3d(handle),
+                MeshMaterial3d(material_handle),
+                position(first_reference + i),
+                ReferenceTile { index: i },
+                Name::new(reference.name.clone()),
+            ));
+        }
+
         evo_state.grid_spawn_requested = false;
         evo_state.redraw_requested = false;
     }
 }
 
+/// Turns sculpt mesh data into a renderable Bevy mesh.
+fn build_mesh(sculpt_data: sculpt::SculptMeshData) -> Mesh {
+    let mut mesh = Mesh::new(
+        bevy::mesh::PrimitiveTopology::TriangleList,
+        RenderAssetUsages::RENDER_WORLD | RenderAssetUsages::MAIN_WORLD,
+    );
+    sculpt::insert_preview_attributes(&mut mesh, &sculpt_data);
+    mesh
+}
+
+/// Colour of untextured population tiles.
+const TILE_COLOR: Color = Color::srgb(0.8, 0.7, 0.6);
+
+/// Paints the companion texture of tile `index` on its material, or the
+/// plain tile colour when textures are off.
+pub fn apply_tile_texture(
+    material: &mut StandardMaterial,
+    images: &mut Assets<Image>,
+    evo_state: &state::EvoState,
+    index: usize,
+) {
+    if evo_state.texture_enabled {
+        let texture = generator::generate_texture(
+            &evo_state.textures[index],
+            evo_state.image_settings(),
+            generator::TEXTURE_PREVIEW_RESOLUTION,
+        );
+        material.base_color = Color::WHITE;
+        material.base_color_texture = Some(images.add(build_texture(&texture)));
+    } else {
+        material.base_color = TILE_COLOR;
+        material.base_color_texture = None;
+    }
+}
+
+/// Uploads a generated texture as a Bevy image, at 8 bits per channel.
+fn build_texture(texture: &SculptField) -> Image {
+    Image::new(
+        Extent3d {
+            width: texture.width() as u32,
+            height: texture.height() as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        texture
+            .to_rgb8()
+            .into_iter()
+            .flat_map(|[r, g, b]| [r, g, b, 255])
+            .collect(),
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::RENDER_WORLD,
+    )
+}
+
 fn on_click_mesh(
     click: On<Pointer<Press>>,
     mut contexts: EguiContexts,
     mut query: Query<&mut Selectable>,
     mut evo_state: ResMut<state::EvoState>,
 ) {
-    if let Ok(ctx) = contexts.ctx_mut() {
-        if ctx.is_pointer_over_area() {
-            return;
-        }
+    if let Ok(ctx) = contexts.ctx_mut()
+        && ctx.is_pointer_over_area()
+    {
+        return;
     }
 
     if let Ok(mut selectable) = query.get_mut(click.original_event_target()) {